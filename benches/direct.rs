@@ -0,0 +1,40 @@
+// The cost of a typed allocation round trip, to compare the default
+// `Vec`-based path against the `direct` feature.
+//
+// The path is picked at compile time, so compare two runs, saving the first
+// as a baseline:
+//
+//     cargo bench --bench direct -- --save-baseline vec
+//     cargo bench --bench direct --features direct -- --baseline vec
+//
+// Criterion then reports the `direct` numbers as a change from `vec`. Both
+// paths end in the same call to the global allocator, so any difference is
+// the bookkeeping around it, and shows most at small sizes.
+
+#[macro_use]
+extern crate criterion;
+extern crate memalloc;
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion};
+
+const SIZES: &[usize] = &[1, 16, 256, 4096];
+
+fn bench_typed_alloc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("typed_alloc_dealloc");
+
+    for &size in SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| unsafe {
+                let buffer = memalloc::typed_alloc::<u64>(black_box(size));
+                memalloc::typed_dealloc(black_box(buffer), size);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_typed_alloc);
+criterion_main!(benches);
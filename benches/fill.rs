@@ -0,0 +1,32 @@
+#[macro_use]
+extern crate criterion;
+extern crate memalloc;
+
+use std::hint::black_box;
+use std::ptr;
+
+use criterion::Criterion;
+
+const SIZE: usize = 1 << 20;
+
+// Run with `--features simd` to compare the vector stores against
+// `ptr::write_bytes`; without it both are the same.
+fn bench_fill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill");
+    let mut buffer = vec![0u8; SIZE + 1];
+    // An odd offset, so neither fill starts out aligned.
+    let start = unsafe { buffer.as_mut_ptr().add(1) };
+
+    group.bench_function("write_bytes", |b| b.iter(|| unsafe {
+        ptr::write_bytes(black_box(start), 0xCD, SIZE);
+    }));
+
+    group.bench_function("fill_bytes", |b| b.iter(|| unsafe {
+        memalloc::fill_bytes(black_box(start), 0xCD, SIZE);
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_fill);
+criterion_main!(benches);
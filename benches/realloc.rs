@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate criterion;
+extern crate memalloc;
+
+use std::hint::black_box;
+
+use criterion::Criterion;
+
+const STEPS: usize = 64;
+const STEP: usize = 256;
+
+fn bench_grow(c: &mut Criterion) {
+    let mut group = c.benchmark_group("grow");
+
+    group.bench_function("typed_realloc", |b| b.iter(|| unsafe {
+        let mut buffer = memalloc::typed_alloc::<u64>(STEP);
+        for i in 1..STEPS {
+            buffer = memalloc::typed_realloc(buffer, i * STEP, (i + 1) * STEP);
+        }
+        memalloc::typed_dealloc(black_box(buffer), STEPS * STEP);
+    }));
+
+    group.bench_function("grow_from_known", |b| b.iter(|| unsafe {
+        let mut buffer = memalloc::typed_alloc::<u64>(STEP);
+        for i in 1..STEPS {
+            buffer = memalloc::grow_from_known(buffer, i * STEP, (i + 1) * STEP);
+        }
+        memalloc::typed_dealloc(black_box(buffer), STEPS * STEP);
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_grow);
+criterion_main!(benches);
@@ -0,0 +1,132 @@
+// Head-to-head comparisons of this crate against `std::alloc` and `Vec`.
+//
+// Each group runs the same operation three ways across a sweep of sizes, so
+// the numbers read as "overhead of memalloc over the global allocator" per
+// size. The typed functions go through `Vec`, so expect `memalloc` and
+// `vec` to track each other closely, and both to sit just above raw
+// `std_alloc`; a gap that grows with size points at extra copying or
+// filling rather than call overhead. Run in release, where the debug fill
+// is off, or the fill cost will dominate every `memalloc` number.
+//
+// Every iteration frees what it allocates, so the benches can run for as
+// long as Criterion wants.
+
+#[macro_use]
+extern crate criterion;
+extern crate memalloc;
+
+use std::alloc::{self, Layout};
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+const SIZES: &[usize] = &[16, 256, 4096, 65_536, 1 << 20];
+
+fn bench_alloc_dealloc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_dealloc");
+
+    for &size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("memalloc", size), &size, |b, &size| {
+            b.iter(|| unsafe {
+                let buffer = memalloc::allocate(size);
+                memalloc::deallocate(black_box(buffer), size);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("std_alloc", size), &size, |b, &size| {
+            let layout = Layout::array::<u8>(size).unwrap();
+            b.iter(|| unsafe {
+                let buffer = alloc::alloc(layout);
+                alloc::dealloc(black_box(buffer), layout);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("vec", size), &size, |b, &size| {
+            b.iter(|| drop(black_box(Vec::<u8>::with_capacity(size))))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_grow(c: &mut Criterion) {
+    let mut group = c.benchmark_group("grow_doubling");
+
+    // Grow from 16 bytes to `size` by doubling, like a growing buffer would.
+    for &size in &SIZES[1..] {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("memalloc", size), &size, |b, &size| {
+            b.iter(|| unsafe {
+                let (mut buffer, mut cap) = (memalloc::allocate(16), 16);
+                while cap < size {
+                    buffer = memalloc::reallocate(buffer, cap, cap * 2);
+                    cap *= 2;
+                }
+                memalloc::deallocate(black_box(buffer), cap);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("std_alloc", size), &size, |b, &size| {
+            b.iter(|| unsafe {
+                let mut layout = Layout::array::<u8>(16).unwrap();
+                let mut buffer = alloc::alloc(layout);
+                while layout.size() < size {
+                    buffer = alloc::realloc(buffer, layout, layout.size() * 2);
+                    layout = Layout::array::<u8>(layout.size() * 2).unwrap();
+                }
+                alloc::dealloc(black_box(buffer), layout);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("vec", size), &size, |b, &size| {
+            b.iter(|| {
+                let mut buffer = Vec::<u8>::with_capacity(16);
+                while buffer.capacity() < size {
+                    let cap = buffer.capacity();
+                    buffer.reserve_exact(cap * 2);
+                }
+                drop(black_box(buffer));
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_zeroed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_zeroed");
+
+    // `alloc_zeroed` can get pre-zeroed pages from the OS for large sizes,
+    // which allocating and then filling never can.
+    for &size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("memalloc", size), &size, |b, &size| {
+            b.iter(|| unsafe {
+                let buffer = memalloc::allocate(size);
+                memalloc::fill_bytes(buffer, 0, size);
+                memalloc::deallocate(black_box(buffer), size);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("std_alloc", size), &size, |b, &size| {
+            let layout = Layout::array::<u8>(size).unwrap();
+            b.iter(|| unsafe {
+                let buffer = alloc::alloc_zeroed(layout);
+                alloc::dealloc(black_box(buffer), layout);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("vec", size), &size, |b, &size| {
+            b.iter(|| drop(black_box(vec![0u8; size])))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_alloc_dealloc, bench_grow, bench_zeroed);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//! Drives the model in tests/model with operation sequences chosen by the
+//! fuzzer. Run with `cargo fuzz run round_trip`.
+
+#![no_main]
+
+use arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/model/mod.rs"]
+mod model;
+
+use model::{GrowWith, Model, Op, MAX_SIZE, SLOTS};
+
+fn op(u: &mut Unstructured) -> Result<Op> {
+    let slot = u.choose_index(SLOTS)?;
+
+    Ok(match u.choose_index(5)? {
+        0 => Op::Alloc { slot, size: u.choose_index(MAX_SIZE)?, seed: u.arbitrary()? },
+        1 => {
+            let via = *u.choose(&[GrowWith::Reallocate, GrowWith::GrowFromKnown, GrowWith::TryRealloc])?;
+            Op::Grow { slot, extra: u.choose_index(MAX_SIZE)?, seed: u.arbitrary()?, via }
+        }
+        2 => Op::Shrink { slot, size: u.choose_index(MAX_SIZE)? },
+        3 => Op::Write { slot, offset: u.arbitrary()?, byte: u.arbitrary()? },
+        _ => Op::Free { slot },
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let mut model = Model::new();
+
+    while let Ok(op) = op(&mut u) {
+        model.apply(op);
+    }
+});
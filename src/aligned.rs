@@ -0,0 +1,467 @@
+use std::{cmp, mem, ptr};
+use std::mem::MaybeUninit;
+
+use {byte_size, mark_moved, typed_alloc, typed_dealloc};
+
+const WORD: usize = mem::size_of::<usize>();
+
+/// Returns a pointer to `size` bytes of memory aligned to `align`.
+///
+/// The memory is carved out of a larger allocation, with room for the
+/// alignment offset and a word recording it, so it must only be freed with
+/// `dealloc_aligned`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0 or `align` is not a power of two.
+pub unsafe fn alloc_aligned(size: usize, align: usize) -> *mut u8 {
+    debug_assert!(align.is_power_of_two());
+
+    let raw = typed_alloc::<u8>(padded_size(size, align));
+    let offset = WORD + raw.add(WORD).align_offset(align);
+    let aligned = raw.add(offset);
+
+    ptr::write_unaligned(aligned.sub(WORD) as *mut usize, offset);
+
+    aligned
+}
+
+/// Deallocates memory returned by `alloc_aligned`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_aligned(size, align)` with the
+/// same `size` and `align`.
+pub unsafe fn dealloc_aligned(ptr: *mut u8, size: usize, align: usize) {
+    let offset = ptr::read_unaligned(ptr.sub(WORD) as *const usize);
+
+    typed_dealloc(ptr.sub(offset), padded_size(size, align));
+}
+
+/// Resizes memory returned by `alloc_aligned`, returning a pointer to
+/// `new_size` bytes which is still aligned to `align`.
+///
+/// The first `min(old_size, new_size)` bytes are preserved. Resizing to 0
+/// frees the memory and returns a dangling pointer aligned to `align`, and
+/// a dangling pointer with an `old_size` of 0 is allocated afresh, so the
+/// two round-trip.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_aligned(old_size, align)` with
+/// the same `old_size` and `align`, or by `resize_aligned` with the same
+/// `align`, or be dangling if `old_size` is 0. `align` must be a power of
+/// two.
+pub unsafe fn resize_aligned(ptr: *mut u8, old_size: usize, new_size: usize,
+                             align: usize) -> *mut u8 {
+    if old_size == new_size {
+        return ptr;
+    }
+
+    let new_ptr = if new_size == 0 {
+        align as *mut u8
+    } else {
+        let new_ptr = alloc_aligned(new_size, align);
+        ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(old_size, new_size));
+        mark_moved(ptr, old_size);
+        new_ptr
+    };
+
+    if old_size != 0 {
+        dealloc_aligned(ptr, old_size, align);
+    }
+
+    new_ptr
+}
+
+/// Allocates `size` bytes aligned to `align`, runs `f` with a pointer to
+/// them, frees them, and returns the result of `f`.
+///
+/// The memory is freed even if `f` panics, so a scratch buffer for a single
+/// operation needs no cleanup. It is uninitialized when `f` is called, and
+/// the pointer must not be kept past the call.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0 or `align` is not a power of two.
+pub unsafe fn with_aligned<R, F: FnOnce(*mut u8) -> R>(size: usize, align: usize, f: F) -> R {
+    let guard = AlignedGuard { ptr: alloc_aligned(size, align), size, align };
+
+    f(guard.ptr)
+}
+
+/// Returns a pointer to `size` uninitialized values of type `T`, aligned to
+/// `align` or to `mem::align_of::<T>()`, whichever is greater.
+///
+/// Like `alloc_aligned`, which it is built on, but typed, with the slots
+/// wrapped in `MaybeUninit` so they can be initialized one at a time before
+/// being read. Free the memory with `dealloc_aligned_uninit`.
+///
+/// On failure, aborts the process. If the size in bytes overflows a `usize`,
+/// panics with "capacity overflow".
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size in bytes is 0 or `align` is
+/// not a power of two.
+pub unsafe fn alloc_aligned_uninit<T>(size: usize, align: usize) -> *mut MaybeUninit<T> {
+    alloc_aligned(byte_size::<T>(size), cmp::max(align, mem::align_of::<T>()))
+        as *mut MaybeUninit<T>
+}
+
+/// Deallocates memory returned by `alloc_aligned_uninit`.
+///
+/// No destructors are run, so any values initialized in the memory must be
+/// dropped first.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_aligned_uninit::<T>(size, align)`
+/// with the same `T`, `size` and `align`.
+pub unsafe fn dealloc_aligned_uninit<T>(ptr: *mut MaybeUninit<T>, size: usize, align: usize) {
+    dealloc_aligned(ptr as *mut u8, byte_size::<T>(size),
+                    cmp::max(align, mem::align_of::<T>()));
+}
+
+/// Returns a pointer to `size` values of type `T`, aligned to the size of
+/// `T` when that is a power of two.
+///
+/// The alignment is `max(mem::align_of::<T>(), mem::size_of::<T>())` if
+/// `mem::size_of::<T>()` is a power of two, and `mem::align_of::<T>()`
+/// otherwise. With a 16-byte `T` every element then starts on a 16-byte
+/// boundary, so no element straddles a cache line. Free the memory with
+/// `dealloc_self_aligned`.
+///
+/// On failure, aborts the process. If the size in bytes overflows a `usize`,
+/// panics with "capacity overflow".
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size in bytes is 0.
+pub unsafe fn alloc_self_aligned<T>(size: usize) -> *mut T {
+    alloc_aligned_uninit::<T>(size, self_alignment::<T>()) as *mut T
+}
+
+/// Deallocates memory returned by `alloc_self_aligned`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_self_aligned::<T>(size)` with the
+/// same `T` and `size`.
+pub unsafe fn dealloc_self_aligned<T>(ptr: *mut T, size: usize) {
+    dealloc_aligned_uninit(ptr as *mut MaybeUninit<T>, size, self_alignment::<T>());
+}
+
+/// Allocates a row-major buffer of `rows` rows of at least `cols` values of
+/// type `T`, each row starting on an `align`-byte boundary, and returns the
+/// pointer along with the row stride in elements.
+///
+/// The stride is the smallest number of elements, at least `cols`, whose
+/// size in bytes is a multiple of `align`, so every row is padded out to the
+/// next aligned start. Find the start of a row with `row_ptr`, and free the
+/// buffer with `dealloc_2d`. The memory is uninitialized, padding included.
+///
+/// On failure, aborts the process. If the size in bytes overflows a `usize`,
+/// panics with "capacity overflow".
+///
+/// # Safety
+///
+/// Behavior is undefined if the buffer would be 0 bytes, or if `align` is
+/// not a power of two.
+pub unsafe fn alloc_2d<T>(rows: usize, cols: usize, align: usize) -> (*mut T, usize) {
+    let stride = row_stride::<T>(cols, align);
+    let size = rows.checked_mul(stride).expect("capacity overflow");
+
+    (alloc_aligned_uninit::<T>(size, align) as *mut T, stride)
+}
+
+/// Deallocates a buffer returned by `alloc_2d`.
+///
+/// # Safety
+///
+/// `ptr` and `stride` must have been returned by `alloc_2d::<T>(rows, _,
+/// align)` with the same `T`, `rows` and `align`.
+pub unsafe fn dealloc_2d<T>(ptr: *mut T, rows: usize, stride: usize, align: usize) {
+    dealloc_aligned_uninit(ptr as *mut MaybeUninit<T>, rows * stride, align);
+}
+
+/// Returns a pointer to the start of row `row` of a buffer from `alloc_2d`
+/// with the given `stride`.
+///
+/// # Safety
+///
+/// `row` must be less than the number of rows the buffer was allocated
+/// with.
+#[inline]
+pub unsafe fn row_ptr<T>(base: *mut T, stride: usize, row: usize) -> *mut T {
+    base.add(row * stride)
+}
+
+/// Returns a pointer to enough memory to hold `size` values of type `T`,
+/// aligned to the compile-time alignment `ALIGN`.
+///
+/// Unlike `alloc_aligned`, there is no overhead: the memory is allocated as
+/// an array of chunks of `ALIGN` bytes with `#[repr(align(ALIGN))]`, so the
+/// allocator aligns it directly. `ALIGN` may be any power of two from 1 to
+/// 4096, and must be at least `mem::align_of::<T>()`. Free the memory with
+/// `dealloc_aligned_const` with the same `size` and `ALIGN`.
+///
+/// On failure, aborts the process. If the size in bytes overflows a `usize`,
+/// panics with "capacity overflow".
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size in bytes is 0.
+pub unsafe fn alloc_aligned_const<T, const ALIGN: usize>(size: usize) -> *mut T
+        where ConstAlign<ALIGN>: Alignment {
+    assert!(ALIGN >= mem::align_of::<T>(), "`ALIGN` is less than the alignment of `T`");
+
+    typed_alloc::<<ConstAlign<ALIGN> as Alignment>::Chunk>(chunks(byte_size::<T>(size), ALIGN))
+        as *mut T
+}
+
+/// Deallocates memory returned by `alloc_aligned_const`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_aligned_const::<T, ALIGN>(size)`
+/// with the same `T`, `size` and `ALIGN`.
+pub unsafe fn dealloc_aligned_const<T, const ALIGN: usize>(ptr: *mut T, size: usize)
+        where ConstAlign<ALIGN>: Alignment {
+    typed_dealloc(ptr as *mut <ConstAlign<ALIGN> as Alignment>::Chunk,
+                  chunks(byte_size::<T>(size), ALIGN));
+}
+
+/// A compile-time alignment, used to pick the chunk type for
+/// `alloc_aligned_const`.
+pub struct ConstAlign<const ALIGN: usize>;
+
+/// Implemented by the `ConstAlign`s supported by `alloc_aligned_const`.
+pub trait Alignment {
+    /// A type whose size and alignment are both the alignment.
+    type Chunk;
+}
+
+macro_rules! alignments {
+    ($($align:expr => $chunk:ident),*) => {
+        $(
+            /// A chunk of bytes of its own alignment.
+            #[repr(C, align($align))]
+            pub struct $chunk([u8; $align]);
+
+            impl Alignment for ConstAlign<$align> {
+                type Chunk = $chunk;
+            }
+        )*
+    }
+}
+
+alignments! {
+    1 => Align1, 2 => Align2, 4 => Align4, 8 => Align8, 16 => Align16, 32 => Align32,
+    64 => Align64, 128 => Align128, 256 => Align256, 512 => Align512, 1024 => Align1024,
+    2048 => Align2048, 4096 => Align4096
+}
+
+/// The number of chunks of `align` bytes needed to hold `bytes` bytes.
+#[inline]
+fn chunks(bytes: usize, align: usize) -> usize {
+    bytes.div_ceil(align)
+}
+
+/// The size of the underlying allocation backing an aligned allocation.
+#[inline]
+fn padded_size(size: usize, align: usize) -> usize {
+    size.checked_add(WORD + align - 1).expect("capacity overflow")
+}
+
+/// Frees the buffer of `with_aligned` when dropped, including while
+/// unwinding.
+struct AlignedGuard {
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+}
+
+impl Drop for AlignedGuard {
+    fn drop(&mut self) {
+        unsafe { dealloc_aligned(self.ptr, self.size, self.align) };
+    }
+}
+
+/// The alignment `alloc_self_aligned` uses for `T`.
+fn self_alignment<T>() -> usize {
+    if mem::size_of::<T>().is_power_of_two() {
+        cmp::max(mem::align_of::<T>(), mem::size_of::<T>())
+    } else {
+        mem::align_of::<T>()
+    }
+}
+
+/// The smallest number of values of type `T`, at least `cols`, which fill a
+/// whole number of `align`-byte units.
+fn row_stride<T>(cols: usize, align: usize) -> usize {
+    let size = mem::size_of::<T>();
+    if size == 0 {
+        return cols;
+    }
+
+    // `align` is a power of two, so `size * stride` is a multiple of it
+    // exactly when `stride` is a multiple of `align` over the largest power
+    // of two dividing both.
+    let common = 1 << cmp::min(size.trailing_zeros(), align.trailing_zeros());
+    cols.div_ceil(align / common).checked_mul(align / common).expect("capacity overflow")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use {alloc_aligned, alloc_aligned_const, dealloc_aligned, dealloc_aligned_const};
+    use resize_aligned;
+    use {alloc_aligned_uninit, dealloc_aligned_uninit};
+    use {alloc_self_aligned, dealloc_self_aligned};
+    use {alloc_2d, dealloc_2d, row_ptr};
+    use with_aligned;
+
+    #[test]
+    fn test_alloc_aligned() {
+        for &align in &[1, 2, 8, 32, 64, 4096] {
+            unsafe {
+                let buffer = alloc_aligned(100, align);
+                assert_eq!(buffer as usize % align, 0);
+
+                ptr::write_bytes(buffer, 0xAB, 100);
+                assert_eq!(ptr::read(buffer.offset(99)), 0xAB);
+
+                dealloc_aligned(buffer, 100, align);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alloc_aligned_const() {
+        unsafe {
+            let buffer = alloc_aligned_const::<f32, 32>(10);
+            assert_eq!(buffer as usize % 32, 0);
+
+            for i in 0..10 {
+                ptr::write(buffer.add(i), i as f32);
+            }
+            assert_eq!(ptr::read(buffer.add(9)), 9.0);
+
+            dealloc_aligned_const::<f32, 32>(buffer, 10);
+
+            let page = alloc_aligned_const::<u8, 4096>(1);
+            assert_eq!(page as usize % 4096, 0);
+            dealloc_aligned_const::<u8, 4096>(page, 1);
+        }
+    }
+
+    #[test]
+    fn test_resize_aligned() {
+        unsafe {
+            let buffer = alloc_aligned(100, 64);
+            for i in 0..100 {
+                ptr::write(buffer.add(i), i as u8);
+            }
+
+            let grown = resize_aligned(buffer, 100, 1000, 64);
+            assert_eq!(grown as usize % 64, 0);
+            for i in 0..100 {
+                assert_eq!(ptr::read(grown.add(i)), i as u8);
+            }
+            ptr::write_bytes(grown.add(100), 0xFF, 900);
+
+            let shrunk = resize_aligned(grown, 1000, 10, 64);
+            assert_eq!(shrunk as usize % 64, 0);
+            for i in 0..10 {
+                assert_eq!(ptr::read(shrunk.add(i)), i as u8);
+            }
+
+            let empty = resize_aligned(shrunk, 10, 0, 64);
+            assert_eq!(empty as usize % 64, 0);
+
+            let regrown = resize_aligned(empty, 0, 50, 64);
+            assert_eq!(regrown as usize % 64, 0);
+            dealloc_aligned(regrown, 50, 64);
+        }
+    }
+
+    #[test]
+    fn test_alloc_aligned_uninit() {
+        unsafe {
+            let buffer = alloc_aligned_uninit::<u16>(50, 32);
+            assert_eq!(buffer as usize % 32, 0);
+
+            for i in 0..50 {
+                (*buffer.add(i)).write(i as u16 * 3);
+            }
+            assert_eq!((*buffer.add(49)).assume_init_read(), 147);
+
+            dealloc_aligned_uninit(buffer, 50, 32);
+        }
+    }
+
+    #[test]
+    fn test_alloc_self_aligned() {
+        assert_eq!(super::self_alignment::<[u32; 4]>(), 16);
+        assert_eq!(super::self_alignment::<[u8; 3]>(), 1);
+        assert_eq!(super::self_alignment::<u64>(), 8);
+
+        unsafe {
+            for _ in 0..16 {
+                let buffer = alloc_self_aligned::<[u32; 4]>(10);
+                assert_eq!(buffer as usize % 16, 0);
+                ptr::write(buffer.add(9), [1, 2, 3, 4]);
+                dealloc_self_aligned(buffer, 10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alloc_2d() {
+        assert_eq!(super::row_stride::<u8>(5, 16), 16);
+        assert_eq!(super::row_stride::<u32>(5, 16), 8);
+        assert_eq!(super::row_stride::<[u8; 3]>(5, 4), 8);
+        assert_eq!(super::row_stride::<u64>(5, 4), 5);
+
+        unsafe {
+            let (buffer, stride) = alloc_2d::<u16>(3, 5, 32);
+            assert_eq!(stride, 16);
+
+            for r in 0..3 {
+                let row = row_ptr(buffer, stride, r);
+                assert_eq!(row as usize % 32, 0);
+                for c in 0..5 {
+                    ptr::write(row.add(c), (r * 10 + c) as u16);
+                }
+            }
+            assert_eq!(ptr::read(row_ptr(buffer, stride, 2).add(4)), 24);
+            assert_eq!(ptr::read(buffer.add(stride + 3)), 13);
+
+            dealloc_2d(buffer, 3, stride, 32);
+        }
+    }
+
+    #[test]
+    fn test_with_aligned() {
+        let sum = unsafe {
+            with_aligned(64 * 4, 64, |buffer| {
+                assert_eq!(buffer as usize % 64, 0);
+
+                // Fill it in 16-byte lanes, as vector stores would.
+                let lanes = buffer as *mut [u32; 4];
+                for i in 0..16 {
+                    ptr::write(lanes.add(i), [i as u32; 4]);
+                }
+                (0..16).map(|i| ptr::read(lanes.add(i))[3]).sum::<u32>()
+            })
+        };
+        assert_eq!(sum, 120);
+    }
+}
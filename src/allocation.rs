@@ -0,0 +1,274 @@
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use {alloc_zst_aware, dealloc_zst_aware};
+
+/// An owned allocation of `T`s which tracks how many of its leading
+/// elements are initialized.
+///
+/// Once all `len` elements are initialized the handle can be used like a
+/// `&[T]` or `&mut [T]` through `Deref` and `DerefMut`.
+///
+/// On drop, the `len` initialized elements are dropped and the allocation
+/// is freed.
+pub struct Allocation<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+}
+
+impl<T> Allocation<T> {
+    /// Allocates room for `cap` values of type `T`, none of which are
+    /// initialized.
+    ///
+    /// If `T` is zero-sized, nothing is allocated and the capacity is
+    /// `usize::MAX`. On failure, aborts the process.
+    pub fn new(cap: usize) -> Allocation<T> {
+        let cap = if mem::size_of::<T>() == 0 { usize::MAX } else { cap };
+        let ptr = unsafe { NonNull::new_unchecked(alloc_zst_aware(cap)) };
+
+        Allocation { ptr, len: 0, cap }
+    }
+
+    /// Creates an `Allocation` from a pointer, the number of initialized
+    /// elements and the capacity.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated to hold `cap` values of type `T` by
+    /// this crate, or be dangling if `cap` is 0, such as a pointer returned
+    /// by `into_raw_parts`. The first `len` elements must be initialized.
+    pub unsafe fn from_raw_parts(ptr: *mut T, len: usize, cap: usize) -> Allocation<T> {
+        debug_assert!(len <= cap);
+        Allocation { ptr: NonNull::new_unchecked(ptr), len, cap }
+    }
+
+    /// Decomposes the `Allocation` into its pointer, number of initialized
+    /// elements and capacity, without dropping or freeing anything.
+    ///
+    /// The caller becomes responsible for the memory, which can be freed
+    /// with `dealloc_zst_aware(ptr, cap)`, or turned back into an
+    /// `Allocation` with `from_raw_parts`.
+    pub fn into_raw_parts(self) -> (*mut T, usize, usize) {
+        let parts = (self.ptr.as_ptr(), self.len, self.cap);
+        mem::forget(self);
+
+        parts
+    }
+
+    /// The number of initialized elements.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no initialized elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of elements the allocation has room for.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// A pointer to the start of the allocation.
+    #[inline]
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+
+    /// A mutable pointer to the start of the allocation.
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Sets the number of initialized elements.
+    ///
+    /// # Safety
+    ///
+    /// `len` must be no greater than the capacity, and the first `len`
+    /// elements must be initialized. This is the invariant `Deref`,
+    /// `DerefMut` and `Drop` rely on.
+    #[inline]
+    pub unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.cap);
+        self.len = len;
+    }
+
+    /// Drops all initialized elements, keeping the allocation for reuse.
+    pub fn clear(&mut self) {
+        let elems = &mut **self as *mut [T];
+
+        // Reset the length first so a panicking destructor can't lead to a
+        // double drop.
+        self.len = 0;
+        unsafe { ptr::drop_in_place(elems) };
+    }
+}
+
+/// Views the first `len` elements as a slice.
+///
+/// All `len` elements must be initialized before this is used, which
+/// `set_len` requires of its callers.
+impl<T> Deref for Allocation<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+/// Views the first `len` elements as a mutable slice.
+///
+/// All `len` elements must be initialized before this is used, which
+/// `set_len` requires of its callers.
+impl<T> DerefMut for Allocation<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for Allocation<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(&mut **self as *mut [T]);
+
+            dealloc_zst_aware(self.ptr.as_ptr(), self.cap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::ptr;
+    use {typed_dealloc, Allocation};
+
+    struct DropCounter<'a>(&'a Cell<usize>);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let alloc = Allocation::<i32>::new(4);
+        assert_eq!(alloc.len(), 0);
+        assert!(alloc.is_empty());
+        assert_eq!(alloc.capacity(), 4);
+        assert!(alloc.iter().next().is_none());
+
+        let empty = Allocation::<i32>::new(0);
+        assert_eq!(empty.capacity(), 0);
+        assert_eq!(&*empty, &[] as &[i32]);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let mut alloc = Allocation::<()>::new(4);
+        assert_eq!(alloc.capacity(), usize::MAX);
+
+        // Every element of a zero-sized type is at the same address.
+        unsafe {
+            ptr::write(alloc.as_mut_ptr(), ());
+            alloc.set_len(10);
+        }
+        assert_eq!(&*alloc, &[(); 10]);
+    }
+
+    #[test]
+    fn test_deref_mut_sort() {
+        let mut alloc = Allocation::<i32>::new(5);
+
+        unsafe {
+            for (i, &x) in [4, 1, 5, 3, 2].iter().enumerate() {
+                ptr::write(alloc.as_mut_ptr().add(i), x);
+            }
+            alloc.set_len(5);
+        }
+
+        alloc.sort();
+        assert_eq!(&*alloc, &[1, 2, 3, 4, 5]);
+        assert_eq!(alloc[0], 1);
+        assert_eq!(alloc.iter().sum::<i32>(), 15);
+    }
+
+    #[test]
+    fn test_clear() {
+        let drops = Cell::new(0);
+        let mut alloc = Allocation::<DropCounter>::new(3);
+
+        unsafe {
+            for i in 0..3 {
+                ptr::write(alloc.as_mut_ptr().add(i), DropCounter(&drops));
+            }
+            alloc.set_len(3);
+        }
+
+        alloc.clear();
+        assert_eq!(drops.get(), 3);
+        assert_eq!(alloc.len(), 0);
+        assert_eq!(alloc.capacity(), 3);
+
+        // Clearing an empty allocation is a no-op.
+        alloc.clear();
+        assert_eq!(drops.get(), 3);
+
+        // The storage can be reused.
+        unsafe {
+            ptr::write(alloc.as_mut_ptr(), DropCounter(&drops));
+            alloc.set_len(1);
+        }
+
+        drop(alloc);
+        assert_eq!(drops.get(), 4);
+    }
+
+    #[test]
+    fn test_into_raw_parts_manual_free() {
+        let mut alloc = Allocation::<u32>::new(8);
+        unsafe {
+            ptr::write(alloc.as_mut_ptr(), 17);
+            alloc.set_len(1);
+        }
+
+        let (ptr, len, cap) = alloc.into_raw_parts();
+        assert_eq!((len, cap), (1, 8));
+
+        unsafe {
+            assert_eq!(ptr::read(ptr), 17);
+            typed_dealloc(ptr, cap);
+        }
+    }
+
+    #[test]
+    fn test_raw_parts_round_trip() {
+        let drops = Cell::new(0);
+        let mut alloc = Allocation::<DropCounter>::new(4);
+        unsafe {
+            ptr::write(alloc.as_mut_ptr(), DropCounter(&drops));
+            ptr::write(alloc.as_mut_ptr().add(1), DropCounter(&drops));
+            alloc.set_len(2);
+        }
+
+        let (ptr, len, cap) = alloc.into_raw_parts();
+        assert_eq!(drops.get(), 0);
+
+        let alloc = unsafe { Allocation::from_raw_parts(ptr, len, cap) };
+        assert_eq!(alloc.len(), 2);
+        assert_eq!(alloc.capacity(), 4);
+
+        drop(alloc);
+        assert_eq!(drops.get(), 2);
+    }
+}
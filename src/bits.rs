@@ -0,0 +1,123 @@
+use std::{cmp, mem};
+
+const WORD: usize = mem::size_of::<usize>();
+
+/// Returns the number of set bits in the `len` bytes starting at `ptr`.
+///
+/// Reads a word at a time where alignment permits.
+///
+/// # Safety
+///
+/// `ptr` must be valid for reads of `len` bytes.
+pub unsafe fn count_set_bits(ptr: *const u8, len: usize) -> u64 {
+    let (head, words, tail) = split_words(ptr, len);
+    let mut count = 0;
+
+    for i in 0..head {
+        count += u64::from((*ptr.add(i)).count_ones());
+    }
+
+    let word_ptr = ptr.add(head) as *const usize;
+    for i in 0..words {
+        count += u64::from((*word_ptr.add(i)).count_ones());
+    }
+
+    for i in len - tail..len {
+        count += u64::from((*ptr.add(i)).count_ones());
+    }
+
+    count
+}
+
+/// Returns the index of the first set bit in the `len` bytes starting at
+/// `ptr`, or `None` if every bit is clear.
+///
+/// Bits are numbered from the least significant bit of the first byte, so
+/// bit `i` is bit `i % 8` of byte `i / 8`. Reads a word at a time where
+/// alignment permits.
+///
+/// # Safety
+///
+/// `ptr` must be valid for reads of `len` bytes.
+pub unsafe fn find_first_set(ptr: *const u8, len: usize) -> Option<usize> {
+    let (head, words, tail) = split_words(ptr, len);
+
+    for i in 0..head {
+        let byte = *ptr.add(i);
+        if byte != 0 {
+            return Some(i * 8 + byte.trailing_zeros() as usize);
+        }
+    }
+
+    let word_ptr = ptr.add(head) as *const usize;
+    for i in 0..words {
+        // In little-endian order the first byte in memory holds the lowest
+        // bits, matching the numbering of the byte loops.
+        let word = usize::from_le(*word_ptr.add(i));
+        if word != 0 {
+            return Some((head + i * WORD) * 8 + word.trailing_zeros() as usize);
+        }
+    }
+
+    for i in len - tail..len {
+        let byte = *ptr.add(i);
+        if byte != 0 {
+            return Some(i * 8 + byte.trailing_zeros() as usize);
+        }
+    }
+
+    None
+}
+
+/// Splits `len` bytes at `ptr` into a count of leading bytes before the
+/// first word boundary, a count of whole words, and a count of trailing
+/// bytes.
+fn split_words(ptr: *const u8, len: usize) -> (usize, usize, usize) {
+    let head = cmp::min(ptr.align_offset(mem::align_of::<usize>()), len);
+    let words = (len - head) / WORD;
+    let tail = len - head - words * WORD;
+
+    (head, words, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use {count_set_bits, find_first_set};
+
+    #[test]
+    fn test_count_set_bits() {
+        let mut bytes = [0u8; 37];
+        bytes[0] = 0b1011;
+        bytes[9] = 0xFF;
+        bytes[36] = 0b1000_0000;
+
+        // Every starting offset exercises a different head/word/tail split.
+        for start in 0..8 {
+            let expected = bytes[start..].iter().map(|b| u64::from(b.count_ones())).sum::<u64>();
+            let count = unsafe { count_set_bits(bytes[start..].as_ptr(), 37 - start) };
+            assert_eq!(count, expected);
+        }
+
+        assert_eq!(unsafe { count_set_bits(bytes.as_ptr(), 37) }, 12);
+        assert_eq!(unsafe { count_set_bits(bytes.as_ptr(), 0) }, 0);
+    }
+
+    #[test]
+    fn test_find_first_set() {
+        let mut bytes = [0u8; 37];
+        assert_eq!(unsafe { find_first_set(bytes.as_ptr(), 37) }, None);
+
+        bytes[20] = 0b0100;
+        bytes[30] = 0b0001;
+        assert_eq!(unsafe { find_first_set(bytes.as_ptr(), 37) }, Some(20 * 8 + 2));
+
+        for start in 0..8 {
+            let found = unsafe { find_first_set(bytes[start..].as_ptr(), 37 - start) };
+            assert_eq!(found, Some((20 - start) * 8 + 2));
+        }
+
+        bytes[20] = 0;
+        assert_eq!(unsafe { find_first_set(bytes.as_ptr(), 37) }, Some(30 * 8));
+        assert_eq!(unsafe { find_first_set(bytes.as_ptr(), 30) }, None);
+    }
+}
@@ -0,0 +1,120 @@
+use OwnedSlice;
+
+/// A builder which fills a fresh allocation front to back, one value at a
+/// time, without ever exposing its uninitialized slots.
+///
+/// Values are written at the cursor with `push`, which grows the buffer when
+/// it is full. `finish` hands over the buffer and the number of values
+/// written. If the `Cursor` is dropped before `finish`, for instance while
+/// unwinding from a panic halfway through construction, the values written
+/// so far are dropped and the buffer is freed.
+pub struct Cursor<T> {
+    values: OwnedSlice<T>,
+}
+
+impl<T> Cursor<T> {
+    /// Allocates room for `cap` values of type `T` and places the cursor at
+    /// the start.
+    ///
+    /// A capacity of 0 does not allocate, and a zero-sized `T` never does.
+    /// On failure, aborts the process.
+    pub fn new(cap: usize) -> Cursor<T> {
+        Cursor { values: OwnedSlice::with_capacity(cap) }
+    }
+
+    /// The number of values written so far.
+    #[inline]
+    pub fn written(&self) -> usize {
+        self.values.len()
+    }
+
+    /// The number of values the buffer has room for.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// Writes `value` at the cursor and advances it, doubling the capacity
+    /// if the buffer is full.
+    ///
+    /// On failure, aborts the process.
+    pub fn push(&mut self, value: T) {
+        self.values.push(value);
+    }
+
+    /// Returns a pointer to the values written and their number, giving up
+    /// ownership of both.
+    ///
+    /// The buffer is shrunk to fit the values, so it can be freed with
+    /// `typed_dealloc(ptr, written)` once they have been dropped. If nothing
+    /// was written, or `T` is zero-sized, nothing is allocated and the
+    /// pointer is dangling.
+    pub fn finish(self) -> (*mut T, usize) {
+        let mut values = self.values;
+        values.shrink_to_fit();
+        let (ptr, written, _) = values.into_raw_parts();
+
+        (ptr, written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::slice;
+    use {typed_dealloc, Cursor};
+
+    #[test]
+    fn test_push_past_capacity() {
+        let mut cursor = Cursor::new(2);
+        for i in 0..10u32 {
+            cursor.push(i * i);
+        }
+        assert_eq!(cursor.written(), 10);
+        assert!(cursor.capacity() >= 10);
+
+        let (ptr, written) = cursor.finish();
+        assert_eq!(written, 10);
+        unsafe {
+            assert_eq!(slice::from_raw_parts(ptr, written), [0, 1, 4, 9, 16, 25, 36, 49, 64, 81]);
+            typed_dealloc(ptr, written);
+        }
+    }
+
+    #[test]
+    fn test_drop_before_finish() {
+        struct DropCounter<'a>(&'a Cell<usize>);
+
+        impl<'a> Drop for DropCounter<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Cell::new(0);
+        let mut cursor = Cursor::new(0);
+        for _ in 0..5 {
+            cursor.push(DropCounter(&drops));
+        }
+        drop(cursor);
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn test_finish_empty() {
+        let (_, written) = Cursor::<String>::new(8).finish();
+        assert_eq!(written, 0);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let mut cursor = Cursor::new(2);
+        assert_eq!(cursor.capacity(), usize::MAX);
+        for _ in 0..10 {
+            cursor.push(());
+        }
+
+        let (_, written) = cursor.finish();
+        assert_eq!(written, 10);
+    }
+}
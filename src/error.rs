@@ -0,0 +1,30 @@
+use std::error::Error;
+use std::fmt;
+
+/// The reason a fallible allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// The requested size in bytes overflowed `isize::MAX`.
+    CapacityOverflow,
+    /// The allocator could not provide the requested memory.
+    OutOfMemory,
+    /// The requested size in bytes exceeded the limit set by
+    /// `set_max_allocation`.
+    ExceedsLimit,
+    /// The size claimed for an existing allocation did not match its actual
+    /// capacity.
+    SizeMismatch,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocError::CapacityOverflow => f.write_str("capacity overflow"),
+            AllocError::OutOfMemory => f.write_str("out of memory"),
+            AllocError::ExceedsLimit => f.write_str("allocation exceeds the configured limit"),
+            AllocError::SizeMismatch => f.write_str("allocation size does not match its capacity"),
+        }
+    }
+}
+
+impl Error for AllocError {}
@@ -0,0 +1,72 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A global allocator which passes requests on to the system allocator, but
+/// fails the `n`th allocation and every one after it, for testing how code
+/// handles running out of memory.
+///
+/// Install it in a test binary with `#[global_allocator]`, then arm it with
+/// `reset` right before the code under test:
+///
+/// ```no_run
+/// extern crate memalloc;
+///
+/// use memalloc::{try_typed_alloc, FailAfter};
+///
+/// #[global_allocator]
+/// static ALLOC: FailAfter = FailAfter::new(usize::MAX);
+///
+/// fn main() {
+///     ALLOC.reset(1);
+///     assert!(unsafe { try_typed_alloc::<u8>(16) }.is_err());
+///     ALLOC.reset(usize::MAX);
+/// }
+/// ```
+///
+/// Every allocation in the process counts, including the test harness's
+/// own, so keep the armed window short. Reallocations count as allocations;
+/// frees always succeed.
+pub struct FailAfter {
+    remaining: AtomicUsize,
+}
+
+impl FailAfter {
+    /// Creates an allocator which fails the `n`th allocation and every one
+    /// after it. An `n` of 0 or 1 fails the very next one, and `usize::MAX`
+    /// in practice never fails.
+    pub const fn new(n: usize) -> FailAfter {
+        FailAfter { remaining: AtomicUsize::new(n) }
+    }
+
+    /// Restarts the count, so that the `n`th allocation from now fails.
+    pub fn reset(&self, n: usize) {
+        self.remaining.store(n, Ordering::SeqCst);
+    }
+
+    /// Counts an allocation, returning whether it may go ahead.
+    fn admit(&self) -> bool {
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst,
+                          |remaining| if remaining > 1 { Some(remaining - 1) } else { None })
+            .is_ok()
+    }
+}
+
+unsafe impl GlobalAlloc for FailAfter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.admit() { System.alloc(layout) } else { ptr::null_mut() }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if self.admit() { System.alloc_zeroed(layout) } else { ptr::null_mut() }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if self.admit() { System.realloc(ptr, layout, new_size) } else { ptr::null_mut() }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
@@ -0,0 +1,124 @@
+//! Filling memory with a byte, used for zeroing and for the debug fill
+//! patterns.
+//!
+//! With the `simd` feature, large fills use 32-byte AVX2 stores on x86-64,
+//! or 16-byte NEON stores on AArch64, when the CPU supports them. Otherwise
+//! they fall back to `ptr::write_bytes`.
+
+use std::ptr;
+
+/// Fills shorter than this many bytes always use `ptr::write_bytes`, since
+/// detecting CPU features and aligning the stores costs more than it saves.
+#[cfg(feature = "simd")]
+const SIMD_THRESHOLD: usize = 4096;
+
+/// Sets `len` bytes starting at `ptr` to `byte`.
+///
+/// With the `simd` feature, fills of at least 4096 bytes use vector stores
+/// where the CPU supports them. The result is always the same as
+/// `ptr::write_bytes(ptr, byte, len)`.
+///
+/// # Safety
+///
+/// `ptr` must be valid for writes of `len` bytes.
+#[inline]
+pub unsafe fn fill_bytes(ptr: *mut u8, byte: u8, len: usize) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if len >= SIMD_THRESHOLD && is_x86_feature_detected!("avx2") {
+            return fill_avx2(ptr, byte, len);
+        }
+    }
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    {
+        if len >= SIMD_THRESHOLD && std::arch::is_aarch64_feature_detected!("neon") {
+            return fill_neon(ptr, byte, len);
+        }
+    }
+
+    ptr::write_bytes(ptr, byte, len)
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn fill_avx2(ptr: *mut u8, byte: u8, len: usize) {
+    use std::arch::x86_64::{__m256i, _mm256_set1_epi8, _mm256_store_si256};
+
+    // Fill up to the first 32-byte boundary, so the vector stores are
+    // aligned, and the remainder after the last one byte by byte.
+    let head = ptr.align_offset(32).min(len);
+    ptr::write_bytes(ptr, byte, head);
+
+    let vector = _mm256_set1_epi8(byte as i8);
+    let mut offset = head;
+    while offset + 32 <= len {
+        _mm256_store_si256(ptr.add(offset) as *mut __m256i, vector);
+        offset += 32;
+    }
+
+    ptr::write_bytes(ptr.add(offset), byte, len - offset);
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+#[target_feature(enable = "neon")]
+unsafe fn fill_neon(ptr: *mut u8, byte: u8, len: usize) {
+    use std::arch::aarch64::{vdupq_n_u8, vst1q_u8};
+
+    let vector = vdupq_n_u8(byte);
+    let mut offset = 0;
+    while offset + 16 <= len {
+        vst1q_u8(ptr.add(offset), vector);
+        offset += 16;
+    }
+
+    ptr::write_bytes(ptr.add(offset), byte, len - offset);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use fill_bytes;
+
+    const LENGTHS: &[usize] = &[0, 1, 15, 16, 17, 31, 32, 33, 4095, 4096, 4097, 10_000, 65_537];
+
+    /// Fills every length at every offset within a 32-byte alignment with
+    /// `fill`, checking the bytes written and the canaries around them.
+    fn check_fill(fill: unsafe fn(*mut u8, u8, usize)) {
+        let mut buffer = vec![0u8; 65_537 + 64];
+
+        for &len in LENGTHS {
+            for offset in 0..33 {
+                for b in buffer.iter_mut() {
+                    *b = 0x11;
+                }
+
+                unsafe { fill(buffer.as_mut_ptr().add(offset), 0xA5, len) };
+
+                let mut expected = vec![0x11u8; buffer.len()];
+                unsafe { ptr::write_bytes(expected.as_mut_ptr().add(offset), 0xA5, len) };
+                assert!(buffer == expected, "len {} at offset {}", len, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_bytes() {
+        check_fill(fill_bytes);
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[test]
+    fn test_fill_avx2() {
+        if is_x86_feature_detected!("avx2") {
+            check_fill(|ptr, byte, len| unsafe { super::fill_avx2(ptr, byte, len) });
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    #[test]
+    fn test_fill_neon() {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            check_fill(|ptr, byte, len| unsafe { super::fill_neon(ptr, byte, len) });
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Allocations of a header followed by an array, like a C struct ending in a
+//! flexible array member.
+//!
+//! The block is laid out as the header `H` at offset 0, then padding up to
+//! `mem::align_of::<T>()`, then the array of `T`:
+//!
+//! ```text
+//! offset = round_up(size_of::<H>(), align_of::<T>())
+//! total  = offset + array_len * size_of::<T>()
+//! ```
+//!
+//! The offset depends only on `H` and `T`, never on the length, so resizing
+//! the array only changes the total size, and the header and the kept
+//! elements are already at the right place in a block copied byte for byte.
+
+use std::{cmp, mem};
+
+use {alloc_aligned, dealloc_aligned, resize_aligned};
+
+/// Returns a pointer to an uninitialized block with room for a header `H`
+/// followed by `array_len` values of type `T`.
+///
+/// The block is aligned for both `H` and `T`. The header is at the returned
+/// base, and `flexible_array` finds the array. Free the block with
+/// `dealloc_flexible`.
+///
+/// On failure, aborts the process. If the size in bytes overflows a `usize`,
+/// panics with "capacity overflow".
+///
+/// # Safety
+///
+/// The header and the array must be initialized before they are read.
+pub unsafe fn alloc_flexible<H, T>(array_len: usize) -> *mut u8 {
+    alloc_aligned(total_size::<H, T>(array_len), alignment::<H, T>())
+}
+
+/// Resizes a block from `alloc_flexible` from `old_array_len` to
+/// `new_array_len` array elements, returning the new base.
+///
+/// The header and the first `min(old_array_len, new_array_len)` elements are
+/// preserved. Since the array starts at the same offset whatever its length,
+/// this reallocates the block to the new total size and copies the bytes up
+/// to the shorter of the two ends, which covers both. If the block was
+/// relocated, the memory at the passed-in base is freed.
+///
+/// On failure, aborts the process. If the size in bytes overflows a `usize`,
+/// panics with "capacity overflow".
+///
+/// # Safety
+///
+/// `base` must have been returned by `alloc_flexible::<H, T>(old_array_len)`
+/// or `realloc_flexible::<H, T>(_, _, old_array_len)` with the same `H` and
+/// `T`, and not yet freed.
+pub unsafe fn realloc_flexible<H, T>(base: *mut u8, old_array_len: usize,
+                                     new_array_len: usize) -> *mut u8 {
+    resize_aligned(base, total_size::<H, T>(old_array_len), total_size::<H, T>(new_array_len),
+                   alignment::<H, T>())
+}
+
+/// Deallocates a block returned by `alloc_flexible` or `realloc_flexible`.
+///
+/// No destructors are run, so the header and any initialized elements must
+/// be dropped first.
+///
+/// # Safety
+///
+/// `base` must have been returned by `alloc_flexible::<H, T>(array_len)` or
+/// `realloc_flexible::<H, T>(_, _, array_len)` with the same `H`, `T` and
+/// `array_len`.
+pub unsafe fn dealloc_flexible<H, T>(base: *mut u8, array_len: usize) {
+    dealloc_aligned(base, total_size::<H, T>(array_len), alignment::<H, T>());
+}
+
+/// Returns a pointer to the first array element of a block from
+/// `alloc_flexible::<H, T>`.
+///
+/// # Safety
+///
+/// `base` must have been returned by `alloc_flexible` or `realloc_flexible`
+/// with the same `H` and `T`.
+#[inline]
+pub unsafe fn flexible_array<H, T>(base: *mut u8) -> *mut T {
+    base.add(array_offset::<H, T>()) as *mut T
+}
+
+/// The offset of the array from the start of the block.
+fn array_offset<H, T>() -> usize {
+    mem::size_of::<H>().next_multiple_of(mem::align_of::<T>())
+}
+
+/// The size in bytes of a block with `array_len` elements, at least 1 so
+/// that it can be allocated even if both `H` and `T` are zero-sized.
+fn total_size<H, T>(array_len: usize) -> usize {
+    array_len.checked_mul(mem::size_of::<T>())
+        .and_then(|bytes| bytes.checked_add(array_offset::<H, T>()))
+        .expect("capacity overflow")
+        .max(1)
+}
+
+/// The alignment of the block.
+fn alignment<H, T>() -> usize {
+    cmp::max(mem::align_of::<H>(), mem::align_of::<T>())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, ptr, slice};
+    use {alloc_flexible, dealloc_flexible, flexible_array, realloc_flexible};
+
+    #[derive(Debug, PartialEq)]
+    struct Header {
+        len: u8,
+        checksum: u32,
+    }
+
+    #[test]
+    fn test_grow_keeps_header() {
+        unsafe {
+            let mut base = alloc_flexible::<Header, u64>(2);
+            ptr::write(base as *mut Header, Header { len: 2, checksum: 0xdead_beef });
+            for i in 0..2 {
+                ptr::write(flexible_array::<Header, u64>(base).add(i), i as u64 + 1);
+            }
+
+            for &len in &[3, 100, 1000] {
+                base = realloc_flexible::<Header, u64>(base, 2, len);
+                assert_eq!(*(base as *const Header), Header { len: 2, checksum: 0xdead_beef });
+                assert_eq!(slice::from_raw_parts(flexible_array::<Header, u64>(base), 2),
+                           &[1, 2]);
+                ptr::write(flexible_array::<Header, u64>(base).add(len - 1), 7);
+
+                base = realloc_flexible::<Header, u64>(base, len, 2);
+            }
+
+            dealloc_flexible::<Header, u64>(base, 2);
+        }
+    }
+
+    #[test]
+    fn test_array_offset() {
+        unsafe {
+            let base = alloc_flexible::<u8, u64>(4);
+            let array = flexible_array::<u8, u64>(base);
+
+            // The one-byte header is padded out to the array's alignment.
+            assert_eq!(array as usize - base as usize, mem::align_of::<u64>());
+            assert!((array as usize).is_multiple_of(mem::align_of::<u64>()));
+
+            dealloc_flexible::<u8, u64>(base, 4);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::cmp;
+
+use {typed_alloc, typed_realloc};
+
+/// A strategy for choosing the capacity to grow a buffer to.
+pub trait GrowthPolicy {
+    /// Returns the capacity to grow a buffer of `current` values to when it
+    /// must hold at least `required` values.
+    ///
+    /// Should be at least `required`; `grow_with_policy` never grows to less.
+    fn next_capacity(&self, current: usize, required: usize) -> usize;
+}
+
+/// Doubles the capacity, or grows to the requirement if that is larger.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Double;
+
+/// Grows the capacity by half, or to the requirement if that is larger.
+///
+/// Named for the golden ratio: growth factors below it let a buffer
+/// eventually reuse the space freed by its earlier allocations, and 1.5 is
+/// the usual choice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GoldenRatio;
+
+/// Grows the capacity by a fixed number of values, or to the requirement if
+/// that is larger.
+#[derive(Debug, Clone, Copy)]
+pub struct Fixed(pub usize);
+
+impl GrowthPolicy for Double {
+    fn next_capacity(&self, current: usize, required: usize) -> usize {
+        cmp::max(required, current.saturating_mul(2))
+    }
+}
+
+impl GrowthPolicy for GoldenRatio {
+    fn next_capacity(&self, current: usize, required: usize) -> usize {
+        cmp::max(required, current.saturating_add(current / 2))
+    }
+}
+
+impl GrowthPolicy for Fixed {
+    fn next_capacity(&self, current: usize, required: usize) -> usize {
+        cmp::max(required, current.saturating_add(self.0))
+    }
+}
+
+/// Grows the allocation referenced by `ptr` to hold at least `required`
+/// values of type `T`, choosing the capacity with `policy`, and returns the
+/// new pointer along with the new capacity.
+///
+/// When `current_cap` is 0 there is no allocation to grow and `ptr` is
+/// ignored. On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `required` is 0.
+///
+/// The `current_cap` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the capacity returned by previous reallocations.
+pub unsafe fn grow_with_policy<T, P>(ptr: *mut T, current_cap: usize, required: usize,
+                                     policy: &P) -> (*mut T, usize)
+        where P: GrowthPolicy + ?Sized {
+    let cap = cmp::max(required, policy.next_capacity(current_cap, required));
+
+    if current_cap == 0 {
+        (typed_alloc(cap), cap)
+    } else {
+        (typed_realloc(ptr, current_cap, cap), cap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use {grow_with_policy, typed_dealloc, Double, Fixed, GoldenRatio, GrowthPolicy};
+
+    #[test]
+    fn test_double() {
+        let mut cap = 0;
+        let mut caps = Vec::new();
+        for required in 1..40 {
+            if required > cap {
+                cap = Double.next_capacity(cap, required);
+                caps.push(cap);
+            }
+        }
+
+        assert_eq!(caps, [1, 2, 4, 8, 16, 32, 64]);
+        assert_eq!(Double.next_capacity(4, 100), 100);
+    }
+
+    #[test]
+    fn test_golden_ratio() {
+        let mut cap = 4;
+        let mut caps = Vec::new();
+        for required in 5..40 {
+            if required > cap {
+                cap = GoldenRatio.next_capacity(cap, required);
+                caps.push(cap);
+            }
+        }
+
+        assert_eq!(caps, [6, 9, 13, 19, 28, 42]);
+        assert_eq!(GoldenRatio.next_capacity(0, 3), 3);
+    }
+
+    #[test]
+    fn test_fixed() {
+        assert_eq!(Fixed(16).next_capacity(0, 1), 16);
+        assert_eq!(Fixed(16).next_capacity(16, 17), 32);
+        assert_eq!(Fixed(16).next_capacity(16, 100), 100);
+    }
+
+    #[test]
+    fn test_grow_with_policy() {
+        unsafe {
+            let (buffer, cap) = grow_with_policy::<u32, _>(ptr::null_mut(), 0, 3, &Fixed(8));
+            assert_eq!(cap, 8);
+            ptr::write(buffer, 5);
+
+            let (buffer, cap) = grow_with_policy(buffer, cap, 9, &Double);
+            assert_eq!(cap, 16);
+            assert_eq!(ptr::read(buffer), 5);
+
+            let policy: &dyn GrowthPolicy = &GoldenRatio;
+            let (buffer, cap) = grow_with_policy(buffer, cap, 17, policy);
+            assert_eq!(cap, 24);
+            assert_eq!(ptr::read(buffer), 5);
+
+            typed_dealloc(buffer, cap);
+        }
+    }
+}
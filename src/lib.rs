@@ -11,17 +11,146 @@
 //! abort the process on OOM. Unfortunately, this limitation is unavoidable if we want
 //! to use only stable APIs.
 //!
+//! What happens on OOM can be changed with `set_oom_behavior`, to panic instead or to
+//! call a handler of your own.
+//!
+//! By default memory is allocated through `Vec`, the original stable-only path. The
+//! `direct` feature allocates with `std::alloc::alloc` and an explicit `Layout`
+//! instead, skipping `Vec`'s bookkeeping. Both are stable today; `direct` is leaner, while
+//! the default depends on nothing but `Vec`'s documented guarantees.
+//!
+//! On unix, the `mmap` feature adds `set_mmap_threshold`, above which `typed_alloc` and
+//! `typed_realloc` take memory from anonymous mappings rather than the heap.
+//!
+
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "libc")]
+extern crate libc;
+
+use std::{cmp, mem, ptr, slice, str};
+use std::alloc::{self, Layout};
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::panic::Location;
+use std::process;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+use oom::oom;
+
+pub use aligned::{alloc_2d, alloc_aligned, alloc_aligned_const, alloc_aligned_uninit,
+                  alloc_self_aligned, dealloc_2d, dealloc_aligned, dealloc_aligned_const,
+                  dealloc_aligned_uninit, dealloc_self_aligned, resize_aligned, row_ptr,
+                  with_aligned, Alignment, ConstAlign};
+pub use allocation::Allocation;
+pub use bits::{count_set_bits, find_first_set};
+pub use cursor::Cursor;
+pub use error::AllocError;
+pub use fail_after::FailAfter;
+pub use fill::fill_bytes;
+pub use flexible::{alloc_flexible, dealloc_flexible, flexible_array, realloc_flexible};
+pub use growth::{grow_with_policy, Double, Fixed, GoldenRatio, GrowthPolicy};
+#[cfg(feature = "metrics")]
+pub use metrics::prometheus_metrics;
+#[cfg(all(unix, feature = "mmap"))]
+pub use mmap::{is_mapped, mmap_threshold, set_mmap_threshold};
+pub use oom::{set_oom_behavior, OomBehavior};
+pub use os::{alloc_committed, alloc_hugepage, alloc_on_node, dealloc_hugepage, dealloc_on_node,
+             is_page_aligned, page_size};
+#[cfg(all(unix, feature = "mlock"))]
+pub use os::{alloc_locked, dealloc_locked};
+#[cfg(all(unix, feature = "libc"))]
+pub use os::try_alloc_errno;
+#[cfg(all(feature = "trim", target_os = "linux", target_env = "gnu"))]
+pub use os::trim_heap;
+pub use owned_slice::OwnedSlice;
+pub use pinned::PinnedAlloc;
+pub use pool::Pool;
+pub use pressure::{realloc_pressure_aware, set_pressure_hook, MemoryPressure};
+pub use raw_stack::RawStack;
+pub use raw_vec::RawVec;
+pub use redzone::{RED_ZONE, RED_ZONE_FILL};
+pub use relocatable::Relocatable;
+#[cfg(feature = "stats")]
+pub use registry::{allocation_size, live_allocations, live_bytes, owns, relocated_to,
+                   size_histogram};
+#[cfg(feature = "backtrace")]
+pub use registry::dump_leaks;
+pub use shared::{SharedAlloc, WeakAlloc};
+pub use size_class::{register_size_classes, size_class};
+pub use slice_alloc::SliceAlloc;
+pub use txn::{begin_grow, ReserveTxn};
+pub use writer::BufWriter;
+
+mod aligned;
+mod allocation;
+mod bits;
+mod cursor;
+mod error;
+mod fail_after;
+mod fill;
+mod flexible;
+mod growth;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(all(unix, feature = "mmap"))]
+mod mmap;
+mod oom;
+mod os;
+mod owned_slice;
+mod pinned;
+mod pool;
+mod pressure;
+mod raw_stack;
+mod raw_vec;
+mod redzone;
+mod relocatable;
+mod registry;
+mod shared;
+mod size_class;
+mod slice_alloc;
+mod txn;
+mod writer;
 
-use std::mem;
+/// The capacity chosen by `try_grow_amortized` for a first allocation is
+/// at least this many bytes, so small types start out filling a cache line.
+const MIN_GROWTH_BYTES: usize = 64;
+
+/// The capacity chosen by `try_grow_amortized` for a first allocation is
+/// at least this many elements.
+const MIN_GROWTH_ELEMENTS: usize = 4;
+
+/// The byte freshly allocated memory is filled with in debug builds,
+/// matching the marker MSVC's debug heap uses for uninitialized memory.
+pub const UNINIT_FILL: u8 = 0xCD;
+
+/// The byte the start of an allocation is overwritten with in debug builds
+/// when it is moved to a new address, just before the old memory is freed.
+/// A read through a stale pointer which finds this marker is a read of
+/// memory that has been relocated; with the `stats` feature,
+/// `relocated_to` tells where it went.
+///
+/// Only the functions which move memory by copying it, such as shrinking
+/// with `typed_realloc`, leave the marker. When growing, the allocator moves
+/// and frees the memory itself, out of the crate's reach.
+pub const MOVED_FILL: u8 = 0xDA;
+
+/// The number of bytes at the start of an allocation overwritten with
+/// `MOVED_FILL` when it is moved.
+const MOVED_FILL_LEN: usize = 16;
+
+static MAX_ALLOCATION: AtomicUsize = AtomicUsize::new(usize::MAX);
 
 /// Returns a pointer to `size` bytes of memory aligned to `mem::align_of::<u8>()`.
 ///
 /// On failure, aborts the process.
 ///
+/// # Safety
+///
 /// Behavior is undefined if the requested size is 0.
 #[inline]
 pub unsafe fn allocate(size: usize) -> *mut u8 {
-    ptr_from_vec(Vec::with_capacity(size))
+    typed_alloc(size)
 }
 
 /// Resizes the allocation referenced by `ptr` to `new_size` bytes.
@@ -31,128 +160,2429 @@ pub unsafe fn allocate(size: usize) -> *mut u8 {
 /// If the allocation was relocated, the memory at the passed-in pointer is
 /// undefined after the call.
 ///
+/// # Safety
+///
 /// Behavior is undefined if the requested `new_size` is 0.
 ///
 /// The `old_size` parameter is the size used to create the allocation
 /// referenced by `ptr`, or the `new_size` passed to previous reallocations.
+#[inline]
 pub unsafe fn reallocate(ptr: *mut u8, old_size: usize, new_size: usize) -> *mut u8 {
-    if old_size > new_size {
-        let mut buf = Vec::from_raw_parts(ptr, new_size, old_size);
-        buf.shrink_to_fit();
+    typed_realloc(ptr, old_size, new_size)
+}
 
-        ptr_from_vec(buf)
-    } else if new_size > old_size {
-        let additional = new_size - old_size;
+/// Deallocates the memory referenced by `ptr`.
+///
+/// # Safety
+///
+/// Behavior is undefined if `ptr` is null.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the `new_size` passed to the last reallocation.
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, old_size: usize) {
+    typed_dealloc(ptr, old_size)
+}
+
+/// Returns a pointer to enough memory to hold `size` values of type `T`,
+/// aligned to `mem::align_of::<T>()`.
+///
+/// The memory is uninitialized. In debug builds it is filled with
+/// `UNINIT_FILL` so reads of uninitialized memory are easy to spot.
+/// With the `redzone` feature, debug builds also surround it with red
+/// zones which are checked when it is reallocated or freed; see
+/// `RED_ZONE`.
+///
+/// If size classes are registered for `T`, `size` is first rounded up to
+/// its class; see `register_size_classes`.
+///
+/// The memory comes from a `Vec`, or with the `direct` feature straight from
+/// `std::alloc::alloc` under `Layout::array::<T>(size)`. Both give the same
+/// allocation, so memory from either can be freed by the other.
+/// With the `mmap` feature, sizes from `set_mmap_threshold` up come from an
+/// anonymous mapping instead.
+///
+/// On failure, aborts the process. If the size in bytes overflows `isize`,
+/// panics with "capacity overflow" instead, like `Vec`.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0, or if `T` is
+/// zero-sized, which debug builds catch with a panic. Use `alloc_zst_aware`
+/// for types which may be zero-sized.
+#[inline]
+pub unsafe fn typed_alloc<T>(size: usize) -> *mut T {
+    debug_assert!(mem::size_of::<T>() != 0, "memalloc does not support zero-sized types");
+    let size = size_class::<T>(size);
+    let bytes = byte_size::<T>(size);
+    enforce_max_allocation::<T>(size);
+
+    let ptr = redzone::guard(raw_alloc::<T>(redzone::padded::<T>(size)), size);
+    registry::on_alloc(ptr, bytes);
+
+    debug_fill(ptr, size)
+}
+
+/// Like `typed_alloc`, but records the location of the caller alongside the
+/// allocation, so that if it is never freed `live_allocations` can report
+/// where it came from.
+///
+/// Without the `stats` feature this is the same as `typed_alloc`.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+#[track_caller]
+pub unsafe fn typed_alloc_located<T>(size: usize) -> *mut T {
+    let ptr = typed_alloc(size);
+    registry::set_location(ptr, Location::caller());
+
+    ptr
+}
+
+/// Like `typed_alloc`, but returns a dangling, well-aligned pointer without
+/// allocating if `T` is zero-sized or `size` is 0, as `Vec` does.
+///
+/// Free the memory with `dealloc_zst_aware`.
+///
+/// # Safety
+///
+/// The values must be initialized before they are read.
+#[inline]
+pub unsafe fn alloc_zst_aware<T>(size: usize) -> *mut T {
+    if mem::size_of::<T>() == 0 || size == 0 {
+        ptr::NonNull::dangling().as_ptr()
+    } else {
+        typed_alloc(size)
+    }
+}
+
+/// Deallocates memory returned by `alloc_zst_aware`, doing nothing if `T`
+/// is zero-sized or `size` is 0.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_zst_aware::<T>(size)` with the
+/// same `T` and `size`.
+#[inline]
+pub unsafe fn dealloc_zst_aware<T>(ptr: *mut T, size: usize) {
+    if mem::size_of::<T>() != 0 && size != 0 {
+        typed_dealloc(ptr, size);
+    }
+}
+
+/// Resizes the allocation referenced by `ptr` to hold `new_size` values of
+/// type `T`.
+///
+/// On failure, aborts the process. If the size in bytes overflows `isize`,
+/// panics with "capacity overflow" instead, like `Vec`.
+///
+/// If the allocation was relocated, the memory at the passed-in pointer is
+/// undefined after the call. The first `min(old_size, new_size)` values are
+/// preserved; no destructors are run. Shrinking always copies the values
+/// into a fresh allocation.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the `new_size` passed to previous reallocations.
+pub unsafe fn typed_realloc<T>(ptr: *mut T, old_size: usize, new_size: usize) -> *mut T {
+    let (old_size, new_size) = (size_class::<T>(old_size), size_class::<T>(new_size));
+    let new_bytes = byte_size::<T>(new_size);
+    enforce_max_allocation::<T>(new_size);
 
-        let mut buf = Vec::from_raw_parts(ptr, 0, old_size);
-        buf.reserve_exact(additional);
+    if old_size > new_size {
+        // Shrinking moves the values into a fresh allocation rather than
+        // rebuilding a `Vec` with a length of `new_size`, which would claim
+        // ownership of values the caller never handed over.
+        redzone::check(ptr, old_size);
+        let realloc = registry::begin_realloc(ptr);
+        let new_ptr = redzone::guard(raw_alloc::<T>(redzone::padded::<T>(new_size)), new_size);
+        ptr::copy_nonoverlapping(ptr, new_ptr, new_size);
+        mark_moved(ptr, old_size);
+        raw_dealloc(redzone::base(ptr), redzone::padded::<T>(old_size));
+        realloc.finish(new_ptr, new_bytes);
 
-        ptr_from_vec(buf)
+        new_ptr
+    } else if new_size > old_size {
+        grow_from_known(ptr, old_size, new_size)
     } else {
         ptr
     }
 }
 
-/// Deallocates the memory referenced by `ptr`.
+/// Grows the allocation referenced by `ptr` from exactly `cap` values of
+/// type `T` to `new_cap` values.
+///
+/// This is the grow path of `typed_realloc`, without the shrink and no-op
+/// checks, for callers that already know they are growing.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// `cap` must be the true capacity of the allocation: the size used to
+/// create it, or the capacity granted by the last reallocation. Passing a
+/// smaller size than was allocated, as `typed_realloc` would tolerate when
+/// shrinking, is undefined behavior. `new_cap` must be greater than `cap`.
+#[inline]
+pub unsafe fn grow_from_known<T>(ptr: *mut T, cap: usize, new_cap: usize) -> *mut T {
+    let (cap, new_cap) = (size_class::<T>(cap), size_class::<T>(new_cap));
+    debug_assert!(new_cap > cap);
+    let new_bytes = byte_size::<T>(new_cap);
+    enforce_max_allocation::<T>(new_cap);
+
+    // A `Vec` can't resize a mapping, nor make one.
+    if involves_mapping(ptr, byte_size::<T>(redzone::padded::<T>(new_cap))) {
+        return grow_by_copy(ptr, cap, new_cap, new_bytes);
+    }
+
+    // With a length of 0, the reservation must be asked for the full new
+    // capacity, not just the difference, so no `new_cap - cap` is computed
+    // which could underflow if the caller's sizes are wrong.
+    redzone::check(ptr, cap);
+    let realloc = registry::begin_realloc(ptr);
+    let mut buf = Vec::from_raw_parts(redzone::base(ptr), 0, redzone::padded::<T>(cap));
+    reserve_or_oom(&mut buf, redzone::padded::<T>(new_cap));
+
+    let (new_base, len, granted) = decompose_vec(buf);
+    check_granted::<T>(len, 0, granted, redzone::padded::<T>(new_cap));
+    let new_ptr = redzone::guard(new_base, new_cap);
+    realloc.finish(new_ptr, new_bytes);
+
+    new_ptr
+}
+
+/// Deallocates the memory referenced by `ptr`, which must have been
+/// allocated to hold `old_size` values of type `T`.
+///
+/// No destructors are run.
+///
+/// # Safety
 ///
 /// Behavior is undefined if `ptr` is null.
 ///
 /// The `old_size` parameter is the size used to create the allocation
 /// referenced by `ptr`, or the `new_size` passed to the last reallocation.
+/// In debug builds with the `stats` feature, freeing with a size in bytes
+/// that differs from the one recorded at allocation panics, which catches
+/// frees through the wrong type.
 #[inline]
-pub unsafe fn deallocate(ptr: *mut u8, old_size: usize) {
-    Vec::from_raw_parts(ptr, 0, old_size);
+pub unsafe fn typed_dealloc<T>(ptr: *mut T, old_size: usize) {
+    let old_size = size_class::<T>(old_size);
+    redzone::check(ptr, old_size);
+    registry::on_dealloc(ptr, byte_size::<T>(old_size));
+    raw_dealloc(redzone::base(ptr), redzone::padded::<T>(old_size));
 }
 
-/// A token empty allocation which cannot be read from or written to,
-/// but which can be used as a placeholder when a 0-sized allocation is
-/// required.
-pub fn empty() -> *mut u8 {
-    1 as *mut u8
+/// Like `typed_alloc`, but returns an error instead of aborting the process
+/// if the memory cannot be allocated.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+pub unsafe fn try_typed_alloc<T>(size: usize) -> Result<*mut T, AllocError> {
+    let size = size_class::<T>(size);
+    check_max_allocation::<T>(size)?;
+
+    let base = try_raw_alloc::<T>(redzone::padded::<T>(size)).ok_or_else(|| reserve_error::<T>(size))?;
+    let ptr = redzone::guard(base, size);
+    registry::on_alloc(ptr, size * mem::size_of::<T>());
+
+    Ok(debug_fill(ptr, size))
 }
 
-#[inline]
-fn ptr_from_vec(mut buf: Vec<u8>) -> *mut u8 {
-    let ptr = buf.as_mut_ptr();
-    mem::forget(buf);
+/// Like `typed_realloc`, but returns an error instead of aborting the
+/// process if the allocation cannot be grown.
+///
+/// On error, the original allocation is left intact and still owned by the
+/// caller.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the `new_size` passed to previous reallocations.
+pub unsafe fn try_typed_realloc<T>(ptr: *mut T, old_size: usize,
+                                   new_size: usize) -> Result<*mut T, AllocError> {
+    let (old_size, new_size) = (size_class::<T>(old_size), size_class::<T>(new_size));
+    check_max_allocation::<T>(new_size)?;
 
-    ptr
+    let new_bytes = redzone::padded::<T>(new_size).saturating_mul(mem::size_of::<T>());
+    if new_size > old_size && involves_mapping(ptr, new_bytes) {
+        // A `Vec` can't resize a mapping, nor make one.
+        redzone::check(ptr, old_size);
+        let realloc = registry::begin_realloc(ptr);
+        let new_base = try_raw_alloc::<T>(redzone::padded::<T>(new_size))
+            .ok_or_else(|| reserve_error::<T>(new_size))?;
+        let new_ptr = redzone::guard(new_base, new_size);
+        ptr::copy_nonoverlapping(ptr, new_ptr, old_size);
+        mark_moved(ptr, old_size);
+        raw_dealloc(redzone::base(ptr), redzone::padded::<T>(old_size));
+        realloc.finish(new_ptr, new_size * mem::size_of::<T>());
+
+        Ok(new_ptr)
+    } else if new_size > old_size {
+        redzone::check(ptr, old_size);
+        // Dropped on failure, putting the record back.
+        let realloc = registry::begin_realloc(ptr);
+        let mut buf = Vec::from_raw_parts(redzone::base(ptr), 0, redzone::padded::<T>(old_size));
+        match buf.try_reserve_exact(redzone::padded::<T>(new_size)) {
+            Ok(()) => {
+                let (new_base, len, cap) = decompose_vec(buf);
+                check_granted::<T>(len, 0, cap, redzone::padded::<T>(new_size));
+                let new_ptr = redzone::guard(new_base, new_size);
+                realloc.finish(new_ptr, new_size * mem::size_of::<T>());
+
+                Ok(new_ptr)
+            },
+            Err(_) => {
+                mem::forget(buf);
+                Err(reserve_error::<T>(new_size))
+            }
+        }
+    } else {
+        Ok(typed_realloc(ptr, old_size, new_size))
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::ptr;
-    use {allocate, reallocate, deallocate, empty};
+/// Like `try_typed_realloc`, but double-checks `claimed_old_size` against
+/// the allocation's actual capacity when the caller knows it.
+///
+/// If `actual_cap_hint` is `Some` and differs from `claimed_old_size`, the
+/// allocation is left untouched and `AllocError::SizeMismatch` is returned,
+/// rather than freeing or growing it with the wrong size. The check is made
+/// in release builds too.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested `new_size` is 0.
+///
+/// When the sizes agree, `ptr` and `claimed_old_size` must satisfy the
+/// requirements of `try_typed_realloc`.
+pub unsafe fn checked_realloc<T>(ptr: *mut T, claimed_old_size: usize,
+                                 actual_cap_hint: Option<usize>,
+                                 new_size: usize) -> Result<*mut T, AllocError> {
+    match actual_cap_hint {
+        Some(actual) if actual != claimed_old_size => Err(AllocError::SizeMismatch),
+        _ => try_typed_realloc(ptr, claimed_old_size, new_size),
+    }
+}
 
-    #[test]
-    fn test_empty() {
-        let ptr = empty();
-        assert!(ptr != ptr::null_mut());
+/// Grows the allocation referenced by `ptr` to at least `min_cap` values of
+/// type `T`, at least doubling its capacity, and returns the new pointer
+/// along with the capacity granted.
+///
+/// When `old_cap` is 0 there is no allocation to grow and `ptr` is ignored;
+/// a fresh allocation of at least 4 elements, or one cache line, is made.
+///
+/// On error, the original allocation is left intact and still owned by the
+/// caller.
+///
+/// # Safety
+///
+/// The `old_cap` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the capacity granted by previous reallocations.
+pub unsafe fn try_grow_amortized<T>(ptr: *mut T, old_cap: usize,
+                                    min_cap: usize) -> Result<(*mut T, usize), AllocError> {
+    if old_cap == 0 {
+        let min_elements = cmp::max(MIN_GROWTH_ELEMENTS,
+                                    MIN_GROWTH_BYTES / cmp::max(mem::size_of::<T>(), 1));
+        let cap = cmp::max(min_cap, min_elements);
+
+        try_typed_alloc(cap).map(|ptr| (ptr, cap))
+    } else {
+        let cap = cmp::max(min_cap, old_cap.saturating_mul(2));
+
+        try_typed_realloc(ptr, old_cap, cap).map(|ptr| (ptr, cap))
     }
+}
 
-    #[test]
-    fn test_allocate() {
-        let buffer = unsafe { allocate(8) };
+/// Resizes the allocation referenced by `ptr` to the smallest power of two
+/// that holds `min_cap` values of type `T`, and returns the new pointer
+/// along with that capacity.
+///
+/// Meant for hash tables and ring buffers whose capacity must be a power of
+/// two. A `min_cap` of 0 is treated as 1. When `old_cap` is 0 there is no
+/// allocation to grow and `ptr` is ignored. If `old_cap` is already larger
+/// than the power of two, the allocation shrinks to it.
+///
+/// On failure, aborts the process. If no power of two of at least `min_cap`
+/// fits in a `usize`, panics with "capacity overflow".
+///
+/// # Safety
+///
+/// The `old_cap` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the capacity returned by previous reallocations.
+pub unsafe fn grow_to_pow2<T>(ptr: *mut T, old_cap: usize, min_cap: usize) -> (*mut T, usize) {
+    let cap = cmp::max(min_cap, 1).checked_next_power_of_two().expect("capacity overflow");
 
-        assert!(buffer != ptr::null_mut());
+    if old_cap == 0 {
+        (typed_alloc(cap), cap)
+    } else {
+        (typed_realloc(ptr, old_cap, cap), cap)
+    }
+}
 
-        unsafe {
-            ptr::write(buffer.offset(0), 8);
-            ptr::write(buffer.offset(1), 4);
-            ptr::write(buffer.offset(3), 5);
-            ptr::write(buffer.offset(5), 3);
-            ptr::write(buffer.offset(7), 6);
+/// Resizes an allocation of `old_count` values of type `Src` into an
+/// allocation of `new_count` values of type `Dst`, preserving the first
+/// `min` bytes of both.
+///
+/// If `Src` and `Dst` have the same alignment the memory is reallocated at
+/// the byte level, otherwise it is moved into a fresh allocation aligned
+/// for `Dst`. The memory at the passed-in pointer is undefined after the
+/// call.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if either the old or the new size in bytes is 0.
+///
+/// The `old_count` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the `new_size` passed to previous reallocations.
+pub unsafe fn realloc_typed<Src, Dst>(ptr: *mut Src, old_count: usize,
+                                      new_count: usize) -> *mut Dst {
+    let (old_count, new_count) = (size_class::<Src>(old_count), size_class::<Dst>(new_count));
+    let old_layout = Layout::array::<Src>(old_count).expect("capacity overflow");
+    let new_layout = Layout::array::<Dst>(new_count).expect("capacity overflow");
+    enforce_max_allocation::<Dst>(new_count);
 
-            assert_eq!(ptr::read(buffer.offset(0)), 8);
-            assert_eq!(ptr::read(buffer.offset(1)), 4);
-            assert_eq!(ptr::read(buffer.offset(3)), 5);
-            assert_eq!(ptr::read(buffer.offset(5)), 3);
-            assert_eq!(ptr::read(buffer.offset(7)), 6);
-        };
+    // Red zones change the layout of the underlying allocation, so with them
+    // the memory is always moved.
+    // Mappings can't be resized by the global allocator either.
+    if old_layout.align() == new_layout.align() && !redzone::ENABLED
+            && !involves_mapping(ptr, new_layout.size()) {
+        let realloc = registry::begin_realloc(ptr as *mut u8);
+        let new_ptr = alloc::realloc(ptr as *mut u8, old_layout, new_layout.size());
+        if new_ptr.is_null() {
+            oom(new_layout);
+        }
+        realloc.finish(new_ptr, new_layout.size());
 
-        unsafe { deallocate(buffer, 8); }
+        new_ptr as *mut Dst
+    } else {
+        let new_ptr = typed_alloc::<Dst>(new_count);
+        ptr::copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8,
+                                 cmp::min(old_layout.size(), new_layout.size()));
+        mark_moved(ptr, old_count);
+        typed_dealloc(ptr, old_count);
 
-        // Try a large buffer
-        let buffer = unsafe { allocate(1024 * 1024) };
-        assert!(buffer != ptr::null_mut());
+        new_ptr
+    }
+}
 
-        unsafe {
-            ptr::write(buffer.offset(1024 * 1024 - 1), 12);
-            assert_eq!(ptr::read(buffer.offset(1024 * 1024 - 1)), 12);
-        };
+/// Resizes the allocation referenced by `ptr` toward `new_size` values of
+/// type `T`, but never below `floor`, and returns the new pointer along with
+/// the resulting capacity.
+///
+/// Useful for reusable buffers which should not shrink so far that they
+/// have to be grown again. On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if both `new_size` and `floor` are 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the capacity returned by previous reallocations.
+pub unsafe fn realloc_floor<T>(ptr: *mut T, old_size: usize, new_size: usize,
+                               floor: usize) -> (*mut T, usize) {
+    let cap = cmp::max(new_size, floor);
 
-        unsafe { deallocate(buffer, 1024 * 1024); }
+    (typed_realloc(ptr, old_size, cap), cap)
+}
+
+/// Changes the length of the allocation referenced by `ptr` within its
+/// existing capacity of `cap` values, which never involves the allocator, and
+/// returns `ptr`.
+///
+/// The length of a buffer is how many of its values are in use, its capacity
+/// how many it has room for. Only a change of capacity needs `typed_realloc`;
+/// calling it for a change of length at best does nothing and at worst
+/// shrinks the allocation out from under the values past the new length.
+/// This function is the explicit way to say the length alone is changing.
+///
+/// # Safety
+///
+/// `new_len` must not exceed `cap`, which is checked in debug builds. The
+/// `cap` parameter is the size used to create the allocation referenced by
+/// `ptr`, or the capacity returned by previous reallocations. Values between
+/// the old and new length are not initialized or dropped.
+#[inline]
+pub unsafe fn set_len_within_cap<T>(ptr: *mut T, cap: usize, new_len: usize) -> *mut T {
+    debug_assert!(new_len <= cap,
+                  "memalloc: length {} exceeds the capacity of {}", new_len, cap);
+
+    ptr
+}
+
+/// Resizes the allocation referenced by `ptr` from `old_layout` to
+/// `new_layout`, preserving the first `min` bytes of both.
+///
+/// When the alignments match the memory is reallocated, possibly in place.
+/// Otherwise it is moved into a fresh allocation under `new_layout`. The
+/// memory at the passed-in pointer is undefined after the call.
+///
+/// This is the shape expected by the standard allocator traits. Memory
+/// from `typed_alloc::<T>(n)` has the layout `Layout::array::<T>(n)`, except
+/// in debug builds with the `redzone` feature.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated under `old_layout`, either by the global
+/// allocator or by the typed functions of this crate, which may have mapped
+/// it. Behavior is undefined if the size of either layout is 0.
+pub unsafe fn realloc_layout(ptr: *mut u8, old_layout: Layout, new_layout: Layout) -> *mut u8 {
+    enforce_max_allocation::<u8>(new_layout.size());
+    let realloc = registry::begin_realloc(ptr);
+
+    // A mapping from `typed_alloc` can't be resized by the global allocator,
+    // so it is moved to the heap.
+    let new_ptr = if old_layout.align() == new_layout.align() && !mapped(ptr) {
+        alloc::realloc(ptr, old_layout, new_layout.size())
+    } else {
+        let new_ptr = alloc::alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(old_layout.size(), new_layout.size()));
+            mark_moved(ptr, old_layout.size());
+            if mapped(ptr) {
+                raw_dealloc(ptr, old_layout.size());
+            } else {
+                alloc::dealloc(ptr, old_layout);
+            }
+        }
+
+        new_ptr
+    };
+
+    if new_ptr.is_null() {
+        oom(new_layout);
     }
+    realloc.finish(new_ptr, new_layout.size());
 
-    #[test]
-    fn test_reallocate() {
-        let mut buffer = unsafe { allocate(8) };
-        assert!(buffer != ptr::null_mut());
+    new_ptr
+}
 
-        buffer = unsafe { reallocate(buffer, 8, 16) };
-        assert!(buffer != ptr::null_mut());
+/// Resizes a buffer owned by C code, allocated with `malloc`, to `new_size`
+/// bytes with `realloc`, preserving the first `min(old_size, new_size)`
+/// bytes.
+///
+/// Unlike `reallocate`, which goes through `Vec` and the global allocator,
+/// the pointer returned stays owned by the C allocator: it must be freed
+/// with C's `free`, or passed back to `realloc_c`, and never to this
+/// crate's deallocation functions. The limit set by `set_max_allocation`
+/// still applies. If the buffer was relocated, the memory at the passed-in
+/// pointer is freed.
+///
+/// Only available with the `libc` feature.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by C's `malloc`, `calloc` or `realloc` and
+/// not yet freed, and be `old_size` bytes long. Behavior is undefined if
+/// `new_size` is 0.
+#[cfg(feature = "libc")]
+pub unsafe fn realloc_c(ptr: *mut u8, old_size: usize, new_size: usize) -> *mut u8 {
+    enforce_max_allocation::<u8>(new_size);
+    if old_size == new_size {
+        return ptr;
+    }
 
-        unsafe {
-            // Put some data in the buffer
-            ptr::write(buffer.offset(0), 8);
-            ptr::write(buffer.offset(1), 4);
-            ptr::write(buffer.offset(5), 3);
-            ptr::write(buffer.offset(7), 6);
-        };
+    let new_ptr = libc::realloc(ptr as *mut libc::c_void, new_size) as *mut u8;
+    if new_ptr.is_null() {
+        oom(Layout::from_size_align_unchecked(new_size, 1));
+    }
 
-        // Allocate so in-place fails.
-        unsafe { allocate(128) };
+    new_ptr
+}
 
-        buffer = unsafe { reallocate(buffer, 16, 32) };
-        assert!(buffer != ptr::null_mut());
+/// Moves the first `len` values of the allocation referenced by `ptr` into
+/// a fresh allocation of exactly `len` values, frees the old allocation, and
+/// returns the new pointer along with its capacity, which is always `len`.
+///
+/// Like shrinking with `typed_realloc`, this guarantees a tight allocation
+/// at the cost of a copy, but it also accepts a `len` of 0, in which case
+/// nothing is allocated and the pointer returned is dangling.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// The `old_cap` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the capacity returned by previous reallocations,
+/// and must be at least `len`.
+pub unsafe fn shrink_exact<T: Copy>(ptr: *mut T, len: usize, old_cap: usize) -> (*mut T, usize) {
+    debug_assert!(len <= old_cap);
 
-        unsafe {
-            // Ensure the data is still there.
-            assert_eq!(ptr::read(buffer.offset(0)), 8);
-            assert_eq!(ptr::read(buffer.offset(1)), 4);
-            assert_eq!(ptr::read(buffer.offset(5)), 3);
-            assert_eq!(ptr::read(buffer.offset(7)), 6);
-        };
+    let new_ptr = if len == 0 {
+        ptr::NonNull::dangling().as_ptr()
+    } else {
+        let new_ptr = typed_alloc::<T>(len);
+        ptr::copy_nonoverlapping(ptr, new_ptr, len);
+        mark_moved(ptr, old_cap);
+        new_ptr
+    };
+    typed_dealloc(ptr, old_cap);
+
+    (new_ptr, len)
+}
+
+/// Resizes the allocation referenced by `ptr` to `new_size` values of type
+/// `T`, always relocating it, and calls `mover(old_elem, new_elem)` to move
+/// each of the first `min(old_size, new_size)` values instead of copying
+/// them bitwise.
+///
+/// Values are moved in order, starting from the first. This allows types
+/// which need fix-ups on relocation, such as self-referential ones, to be
+/// moved. Values past `new_size` are neither moved nor dropped. The old
+/// allocation is freed once every value has been moved.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the `new_size` passed to previous reallocations.
+/// `mover` must initialize `new_elem`, for instance by reading `old_elem`.
+pub unsafe fn realloc_move_with<T, F>(ptr: *mut T, old_size: usize, new_size: usize,
+                                      mut mover: F) -> *mut T
+        where F: FnMut(*mut T, *mut T) {
+    let new_ptr = typed_alloc::<T>(new_size);
+
+    for i in 0..cmp::min(old_size, new_size) {
+        mover(ptr.add(i), new_ptr.add(i));
     }
+    mark_moved(ptr, old_size);
+    typed_dealloc(ptr, old_size);
+
+    new_ptr
 }
 
+/// Moves the first `len` values of the allocation referenced by `ptr` into a
+/// fresh allocation of exactly `len` values of type `Dst`, converting each
+/// with `f`, frees the old allocation, and returns the new pointer along
+/// with its capacity, which is always `len`.
+///
+/// Values are read out of the old buffer, passed to `f` and written to the
+/// new one in order, so `f` takes ownership of each `Src`. When `len` is 0,
+/// nothing is allocated and the pointer returned is dangling.
+///
+/// If `f` panics, the values not yet passed to it and the values it has
+/// already produced are dropped, and both allocations are freed.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// The first `len` values of the allocation referenced by `ptr` must be
+/// initialized, and are moved out by the call. The `old_cap` parameter is
+/// the size used to create the allocation, or the capacity returned by
+/// previous reallocations, and must be at least `len`.
+pub unsafe fn realloc_map<Src, Dst, F>(ptr: *mut Src, len: usize, old_cap: usize,
+                                       mut f: F) -> (*mut Dst, usize)
+        where F: FnMut(Src) -> Dst {
+    debug_assert!(len <= old_cap);
+
+    let dst = if len == 0 { ptr::NonNull::dangling().as_ptr() } else { typed_alloc::<Dst>(len) };
+    let mut guard = MapGuard { src: ptr, len, old_cap, dst, read: 0 };
+
+    while guard.read < len {
+        let i = guard.read;
+        let value = ptr::read(ptr.add(i));
+        guard.read += 1;
+        ptr::write(dst.add(i), f(value));
+    }
+
+    mem::forget(guard);
+    mark_moved(ptr, old_cap);
+    typed_dealloc(ptr, old_cap);
+
+    (dst, len)
+}
+
+/// Allocates a fresh buffer of `new_size` values of type `T` to migrate the
+/// allocation at `old_ptr` into, and returns `(old_ptr, new_ptr)`.
+///
+/// Nothing is copied or freed. The caller moves, and possibly transforms,
+/// the elements itself and then frees the old buffer with
+/// `typed_dealloc(old_ptr, old_size)`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `old_ptr`, or the `new_size` passed to previous
+/// reallocations.
+pub unsafe fn alloc_migration<T>(old_ptr: *mut T, old_size: usize,
+                                 new_size: usize) -> (*mut T, *mut T) {
+    debug_assert!(!old_ptr.is_null() && old_size != 0);
+
+    (old_ptr, typed_alloc(new_size))
+}
+
+/// Grows two allocations of `old_size` values, of types `A` and `B`, to
+/// `new_size` values each, or neither of them.
+///
+/// Useful for keeping parallel arrays the same length. On success, the
+/// passed-in pointers are invalidated and the new pointers are returned. If
+/// either allocation cannot be grown, `None` is returned and both original
+/// allocations are left as they were.
+///
+/// `b` is grown in place where possible, while `a` is moved into a fresh
+/// allocation so that it can be rolled back by freeing it.
+///
+/// # Safety
+///
+/// Behavior is undefined if `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create both allocations, or
+/// the `new_size` passed to previous reallocations.
+pub unsafe fn grow_pair<A, B>(a: *mut A, b: *mut B, old_size: usize,
+                              new_size: usize) -> Option<(*mut A, *mut B)> {
+    let new_a = match try_typed_alloc::<A>(new_size) {
+        Ok(new_a) => new_a,
+        Err(_) => return None,
+    };
+
+    match try_typed_realloc(b, old_size, new_size) {
+        Ok(new_b) => {
+            ptr::copy_nonoverlapping(a, new_a, cmp::min(old_size, new_size));
+            typed_dealloc(a, old_size);
+
+            Some((new_a, new_b))
+        },
+        Err(_) => {
+            typed_dealloc(new_a, new_size);
+            None
+        }
+    }
+}
+
+/// Returns a pointer to enough memory to hold `size` values of type `T`,
+/// which is zeroed in debug builds but left uninitialized in release builds.
+///
+/// This makes tests which accidentally read memory before writing it see
+/// deterministic zeros, without paying for zeroing in production. In
+/// release builds the memory is truly uninitialized, and reading it before
+/// writing it is undefined behavior, so code must never rely on the zeros.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+pub unsafe fn alloc_debug_zeroed<T>(size: usize) -> *mut T {
+    let ptr = typed_alloc::<T>(size);
+
+    if cfg!(debug_assertions) {
+        fill_bytes(ptr as *mut u8, 0, size * mem::size_of::<T>());
+    }
+
+    ptr
+}
+
+/// Allocates `count` buffers of `size` values of type `T` each, returning
+/// a pointer to the start of every buffer.
+///
+/// The buffers are consecutive slices of a single allocation of
+/// `size * count` values, so allocating them costs one call into the
+/// allocator. They must all be freed at once, by passing the whole batch
+/// to `dealloc_batch`. When `count` is 0 nothing is allocated.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+pub unsafe fn alloc_batch<T>(size: usize, count: usize) -> Vec<*mut T> {
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let block = typed_alloc::<T>(size.checked_mul(count).expect("capacity overflow"));
+    (0..count).map(|i| block.add(i * size)).collect()
+}
+
+/// Deallocates a batch of buffers returned by `alloc_batch`.
+///
+/// # Safety
+///
+/// `batch` must have been returned by `alloc_batch(size, _)` with the same
+/// `size`, and must not have been reordered or resized.
+pub unsafe fn dealloc_batch<T>(batch: Vec<*mut T>, size: usize) {
+    if let Some(&block) = batch.first() {
+        typed_dealloc(block, size * batch.len());
+    }
+}
+
+/// Allocates one buffer for every `(element_size, count)` pair in `sizes`,
+/// of `element_size * count` bytes each, or none of them.
+///
+/// The buffers are separate allocations, made in order through the fallible
+/// path. If one fails, or its size overflows, every buffer already made is
+/// freed and `None` is returned, so a structure built from several buffers
+/// never leaks a partial set. Pairs of 0 bytes get a dangling pointer, and
+/// no allocation. The buffers are aligned like those from `allocate`; free
+/// them all with `dealloc_group`, or each with `deallocate`.
+///
+/// The memory is uninitialized, and must be initialized before it is read.
+pub fn alloc_group(sizes: &[(usize, usize)]) -> Option<Vec<*mut u8>> {
+    let mut group = Vec::with_capacity(sizes.len());
+
+    for &(element_size, count) in sizes {
+        let buffer = match element_size.checked_mul(count) {
+            Some(0) => Ok(ptr::NonNull::dangling().as_ptr()),
+            Some(bytes) => unsafe { try_typed_alloc::<u8>(bytes) },
+            None => Err(AllocError::CapacityOverflow),
+        };
+
+        match buffer {
+            Ok(buffer) => group.push(buffer),
+            Err(_) => {
+                let made = group.len();
+                unsafe { dealloc_group(group, &sizes[..made]) };
+                return None;
+            },
+        }
+    }
+
+    Some(group)
+}
+
+/// Deallocates a group of buffers returned by `alloc_group`.
+///
+/// # Safety
+///
+/// `group` must have been returned by `alloc_group(sizes)` with the same
+/// `sizes`, and must not have been reordered.
+pub unsafe fn dealloc_group(group: Vec<*mut u8>, sizes: &[(usize, usize)]) {
+    for (buffer, &(element_size, count)) in group.into_iter().zip(sizes) {
+        if element_size * count != 0 {
+            typed_dealloc(buffer, element_size * count);
+        }
+    }
+}
+
+/// Returns a pointer to an array of `count` values of type `T` which C code
+/// can index with a stride of `sizeof(T)`.
+///
+/// A compile-time check rejects types whose size is zero or not a multiple
+/// of their alignment, the two ways a Rust stride could differ from C's.
+/// `T` must also be `#[repr(C)]` for its layout, and so its size, to match
+/// the C declaration; that is up to the caller. Free the array with
+/// `typed_dealloc(ptr, count)`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `count` is 0.
+pub unsafe fn alloc_ffi_array<T>(count: usize) -> *mut T {
+    const {
+        assert!(mem::size_of::<T>() != 0, "`T` has no C equivalent");
+        assert!(mem::size_of::<T>().is_multiple_of(mem::align_of::<T>()),
+                "the size of `T` is not a multiple of its alignment");
+    }
+
+    typed_alloc(count)
+}
+
+/// Allocates `size` uninitialized values of type `T`, returned as a slice of
+/// `MaybeUninit<T>`.
+///
+/// The memory is not freed automatically; once done with it, free it with
+/// `typed_dealloc(slice.as_mut_ptr() as *mut T, size)`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+///
+/// The caller chooses the lifetime `'a`, and must not use the slice after
+/// the memory is freed. `SliceAlloc` ties the lifetime to the allocation
+/// instead.
+pub unsafe fn alloc_uninit_slice<'a, T>(size: usize) -> &'a mut [MaybeUninit<T>] {
+    slice::from_raw_parts_mut(typed_alloc::<MaybeUninit<T>>(size), size)
+}
+
+/// Allocates room for at least `size` values of type `T` and returns it as
+/// a slice pointer whose length is the capacity granted, like
+/// `Allocator::allocate`.
+///
+/// The capacity is `size` rounded up to the size classes registered for
+/// `T`, if any; see `would_grant`. All of it may be used, and it is the size
+/// to free the memory with: `typed_dealloc(ptr.as_ptr() as *mut T,
+/// ptr.len())`. The memory is uninitialized.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+pub unsafe fn alloc_slice_nonnull<T>(size: usize) -> ptr::NonNull<[T]> {
+    let cap = size_class::<T>(size);
+
+    ptr::NonNull::slice_from_raw_parts(ptr::NonNull::new_unchecked(typed_alloc(cap)), cap)
+}
+
+/// Allocates `size` uninitialized values of type `T` and hands the
+/// allocation to a `Box<[MaybeUninit<T>]>`.
+///
+/// The memory is allocated by this crate, but once boxed it is owned and
+/// freed by `Box` through the global allocator, like any other box, and is
+/// no longer tracked by the `stats` registry. If size classes are registered
+/// for `MaybeUninit<T>` the box covers the whole rounded capacity, so it may
+/// be longer than `size`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+pub unsafe fn alloc_uninit_box<T>(size: usize) -> Box<[MaybeUninit<T>]> {
+    // `Box` would free the memory without its red zones, so it gets memory
+    // of its own.
+    if redzone::ENABLED {
+        let len = size_class::<MaybeUninit<T>>(size);
+        enforce_max_allocation::<T>(len);
+
+        let mut boxed = Box::<[T]>::new_uninit_slice(len);
+        debug_fill(boxed.as_mut_ptr(), len);
+        return boxed;
+    }
+
+    // Nor can it free a mapping, so the memory always comes from the heap.
+    let len = size_class::<MaybeUninit<T>>(size);
+    enforce_max_allocation::<T>(len);
+    let ptr = debug_fill(heap_alloc::<MaybeUninit<T>>(len), len);
+
+    Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len))
+}
+
+/// Allocates exactly `iter.len()` values of type `T` and moves every item
+/// yielded by `iter` into the allocation, returning the pointer and the
+/// number of values written.
+///
+/// If the iterator yields fewer items than it reported, the count returned
+/// is the number actually written, but the allocation still holds
+/// `iter.len()` values and must be freed with that size. When `iter.len()`
+/// is 0 nothing is allocated and the pointer returned is dangling.
+///
+/// If the iterator panics, the values already written are dropped and the
+/// allocation is freed.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// The caller is responsible for dropping the values and freeing the
+/// allocation with `typed_dealloc(ptr, iter.len())`.
+pub unsafe fn alloc_from_iter<T, I>(iter: I) -> (*mut T, usize)
+        where I: ExactSizeIterator<Item = T> {
+    let cap = iter.len();
+    if cap == 0 {
+        return (ptr::NonNull::dangling().as_ptr(), 0);
+    }
+
+    let mut guard = InitGuard { ptr: typed_alloc::<T>(cap), cap, written: 0 };
+
+    for item in iter.take(cap) {
+        ptr::write(guard.ptr.add(guard.written), item);
+        guard.written += 1;
+    }
+
+    let parts = (guard.ptr, guard.written);
+    mem::forget(guard);
+
+    parts
+}
+
+/// Allocates `size` values of type `T`, initializing the value at each
+/// index `i` with `init(i)`.
+///
+/// If `init` returns an error, or panics, the values already initialized
+/// are dropped, the allocation is freed, and the error is returned.
+///
+/// On allocation failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if the requested size is 0.
+///
+/// The caller is responsible for dropping the values and freeing the
+/// allocation with `typed_dealloc(ptr, size)`.
+pub unsafe fn alloc_try_init<T, E, F>(size: usize, mut init: F) -> Result<*mut T, E>
+        where F: FnMut(usize) -> Result<T, E> {
+    let mut guard = InitGuard { ptr: typed_alloc::<T>(size), cap: size, written: 0 };
+
+    while guard.written < size {
+        let value = init(guard.written)?;
+        ptr::write(guard.ptr.add(guard.written), value);
+        guard.written += 1;
+    }
+
+    let ptr = guard.ptr;
+    mem::forget(guard);
+
+    Ok(ptr)
+}
+
+/// Views a fully initialized slice of `MaybeUninit<T>` as a slice of `T`.
+///
+/// # Safety
+///
+/// Every element of `s` must have been initialized, for instance with
+/// `MaybeUninit::write`.
+#[inline]
+pub unsafe fn assume_init_slice<T>(s: &mut [MaybeUninit<T>]) -> &mut [T] {
+    &mut *(s as *mut [MaybeUninit<T>] as *mut [T])
+}
+
+/// Returns the number of values of type `T` between `start` and `end`.
+///
+/// # Safety
+///
+/// Both pointers must point into, or one past the end of, the same
+/// allocation, and `end` must not be before `start`. The distance must be a
+/// whole number of values of type `T`.
+#[inline]
+pub unsafe fn elements_between<T>(start: *const T, end: *const T) -> usize {
+    debug_assert!(end >= start, "`end` is before `start`");
+    end.offset_from(start) as usize
+}
+
+/// Copies the bytes of `s` into the buffer at `ptr`, starting `offset` bytes
+/// in, and returns the number of bytes written, `s.len()`.
+///
+/// # Safety
+///
+/// `ptr` must be valid for writes of `offset + s.len()` bytes.
+#[inline]
+pub unsafe fn write_str(ptr: *mut u8, offset: usize, s: &str) -> usize {
+    ptr::copy_nonoverlapping(s.as_ptr(), ptr.add(offset), s.len());
+    s.len()
+}
+
+/// Views the `len` bytes of the buffer at `ptr`, starting `offset` bytes
+/// in, as a `&str`.
+///
+/// # Safety
+///
+/// `ptr` must be valid for reads of `offset + len` bytes, and those bytes
+/// must be valid UTF-8, such as bytes written by `write_str`.
+///
+/// The caller chooses the lifetime `'a`, and must not use the string after
+/// the buffer is modified or freed.
+#[inline]
+pub unsafe fn read_str<'a>(ptr: *const u8, offset: usize, len: usize) -> &'a str {
+    str::from_utf8_unchecked(slice::from_raw_parts(ptr.add(offset), len))
+}
+
+/// Hands an allocation of bytes to a `bytes::BytesMut` without copying.
+///
+/// `BytesMut` takes ownership of the memory: it is freed through the global
+/// allocator when the last handle to it is dropped, and must no longer be
+/// freed by the caller. Once converted, the allocation is no longer tracked
+/// by the `stats` registry.
+///
+/// Only available with the `bytes` feature.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated by this crate to hold `cap` bytes, or be
+/// dangling if `cap` is 0, and the first `len` bytes must be initialized.
+#[cfg(feature = "bytes")]
+pub unsafe fn into_bytes_mut(ptr: *mut u8, len: usize, cap: usize) -> bytes::BytesMut {
+    debug_assert!(len <= cap);
+    // A dangling pointer has no allocation, nor red zones, to hand over.
+    if cap == 0 {
+        return bytes::BytesMut::new();
+    }
+
+    let cap = size_class::<u8>(cap);
+    registry::on_dealloc(ptr, cap);
+
+    // With red zones, `BytesMut` owns the whole underlying allocation but
+    // starts past the leading zone.
+    redzone::check(ptr, cap);
+    let (buf, zone) = if mapped(ptr) {
+        // `BytesMut` would free a mapping through the global allocator, so
+        // the bytes are copied to the heap.
+        let mut buf = Vec::with_capacity(cap);
+        buf.extend_from_slice(slice::from_raw_parts(ptr, len));
+        raw_dealloc(redzone::base(ptr), redzone::padded::<u8>(cap));
+        (buf, 0)
+    } else {
+        let zone = ptr.offset_from(redzone::base(ptr)) as usize;
+        (Vec::from_raw_parts(redzone::base(ptr), zone + len, redzone::padded::<u8>(cap)), zone)
+    };
+
+    // `BytesMut` only takes a `Vec` through `Bytes`, and converting back
+    // cannot fail or copy since the new handle is unique.
+    let mut bytes = match bytes::Bytes::from(buf).try_into_mut() {
+        Ok(bytes) => bytes,
+        Err(_) => unreachable!(),
+    };
+    bytes::Buf::advance(&mut bytes, zone);
+
+    bytes
+}
+
+/// Returns the capacity, in values of type `T`, that a request for `size`
+/// values would be granted, without allocating.
+///
+/// `Vec`, which backs the typed functions, grants exactly the capacity it is
+/// asked for, so the only rounding is that of the classes installed with
+/// `register_size_classes`, and the answer is exact. Zero-sized types are
+/// granted `usize::MAX`, the capacity `Vec` reports for them. Callers can use
+/// this to size a request so that none of the granted capacity goes to
+/// waste.
+pub fn would_grant<T>(size: usize) -> usize {
+    if mem::size_of::<T>() == 0 {
+        usize::MAX
+    } else {
+        size_class::<T>(size)
+    }
+}
+
+/// Sets the largest allocation, in bytes, this crate will make.
+///
+/// Requests for more than `bytes` bytes abort the process in the infallible
+/// functions and return `AllocError::ExceedsLimit` from the fallible ones.
+/// This guards against untrusted sizes driving allocation. The limit
+/// applies to the total size in bytes, after multiplying by the size of the
+/// element type.
+///
+/// The default is `usize::MAX`, which imposes no limit.
+pub fn set_max_allocation(bytes: usize) {
+    MAX_ALLOCATION.store(bytes, Ordering::Relaxed);
+}
+
+/// Returns the limit set by `set_max_allocation`.
+pub fn max_allocation() -> usize {
+    MAX_ALLOCATION.load(Ordering::Relaxed)
+}
+
+/// A token empty allocation which cannot be read from or written to,
+/// but which can be used as a placeholder when a 0-sized allocation is
+/// required.
+pub fn empty() -> *mut u8 {
+    ptr::NonNull::dangling().as_ptr()
+}
+
+/// Prevents the compiler from reordering memory accesses across this
+/// point, with `SeqCst` ordering.
+///
+/// Placing this after writes that scrub sensitive memory keeps the
+/// compiler from treating them as dead stores and eliminating them. It
+/// emits no machine instructions; see `fence_seqcst` for ordering with
+/// respect to other threads.
+#[inline]
+pub fn compiler_fence_seqcst() {
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// A full `SeqCst` memory fence.
+///
+/// Placing this after writes that scrub sensitive memory ensures the scrub
+/// is visible to other threads before the current thread proceeds. Like
+/// `compiler_fence_seqcst`, it also keeps the compiler from eliminating
+/// the scrub.
+#[inline]
+pub fn fence_seqcst() {
+    atomic::fence(Ordering::SeqCst);
+}
+
+/// Drops the first `written` values of a fresh allocation of `cap` values
+/// and frees it, unless forgotten. Keeps initialization from leaking if it
+/// panics or fails.
+struct InitGuard<T> {
+    ptr: *mut T,
+    cap: usize,
+    written: usize,
+}
+
+impl<T> Drop for InitGuard<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.written));
+            typed_dealloc(self.ptr, self.cap);
+        }
+    }
+}
+
+/// Drops the values of a `realloc_map` in progress and frees both buffers,
+/// unless forgotten. `read` values have been taken out of `src`; all but the
+/// last, which was being converted, have been written to `dst`.
+struct MapGuard<Src, Dst> {
+    src: *mut Src,
+    len: usize,
+    old_cap: usize,
+    dst: *mut Dst,
+    read: usize,
+}
+
+impl<Src, Dst> Drop for MapGuard<Src, Dst> {
+    fn drop(&mut self) {
+        unsafe {
+            let written = self.read.saturating_sub(1);
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.dst, written));
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.src.add(self.read),
+                                                             self.len - self.read));
+
+            if self.len != 0 {
+                typed_dealloc(self.dst, self.len);
+            }
+            typed_dealloc(self.src, self.old_cap);
+        }
+    }
+}
+
+/// Checks a request for `size` values of type `T` against the limit set by
+/// `set_max_allocation`.
+#[inline]
+fn check_max_allocation<T>(size: usize) -> Result<(), AllocError> {
+    let bytes = size.saturating_mul(mem::size_of::<T>());
+
+    if bytes > max_allocation() {
+        Err(AllocError::ExceedsLimit)
+    } else {
+        Ok(())
+    }
+}
+
+/// Like `check_max_allocation`, but aborts the process if the limit is
+/// exceeded.
+#[inline]
+fn enforce_max_allocation<T>(size: usize) {
+    if check_max_allocation::<T>(size).is_err() {
+        let _ = writeln!(io::stderr(),
+                         "memalloc: allocation of {} elements of {} bytes exceeds the limit of {} bytes",
+                         size, mem::size_of::<T>(), max_allocation());
+        process::abort();
+    }
+}
+
+/// The size in bytes of `size` values of type `T`.
+///
+/// Panics with "capacity overflow", as `Vec` does, rather than wrapping
+/// around to a smaller size.
+#[inline]
+fn byte_size<T>(size: usize) -> usize {
+    size.checked_mul(mem::size_of::<T>()).expect("capacity overflow")
+}
+
+/// Fills a fresh allocation of `size` values of type `T` with
+/// `UNINIT_FILL` in debug builds. A no-op in release builds.
+#[inline]
+unsafe fn debug_fill<T>(ptr: *mut T, size: usize) -> *mut T {
+    if cfg!(debug_assertions) {
+        fill_bytes(ptr as *mut u8, UNINIT_FILL, size * mem::size_of::<T>());
+    }
+
+    ptr
+}
+
+/// Overwrites the first bytes of an allocation of `size` values of type `T`
+/// which has been moved elsewhere with `MOVED_FILL` in debug builds, before
+/// it is freed. A no-op in release builds.
+#[inline]
+pub(crate) unsafe fn mark_moved<T>(ptr: *mut T, size: usize) {
+    if cfg!(debug_assertions) {
+        let bytes = size.saturating_mul(mem::size_of::<T>());
+        fill_bytes(ptr as *mut u8, MOVED_FILL, cmp::min(bytes, MOVED_FILL_LEN));
+    }
+}
+
+/// Classifies a failed reservation of `size` values of type `T`.
+fn reserve_error<T>(size: usize) -> AllocError {
+    match size.checked_mul(mem::size_of::<T>()) {
+        Some(bytes) if bytes <= isize::MAX as usize => AllocError::OutOfMemory,
+        _ => AllocError::CapacityOverflow,
+    }
+}
+
+/// Reserves room for exactly `cap` values in the empty `buf`, like
+/// `reserve_exact`, but hands allocation failure to `oom` so it follows
+/// `set_oom_behavior`.
+///
+/// If `oom` unwinds, `buf` is forgotten rather than dropped, so that memory
+/// borrowed from the caller through `Vec::from_raw_parts` stays theirs.
+#[inline]
+fn reserve_or_oom<T>(buf: &mut Vec<T>, cap: usize) {
+    if buf.try_reserve_exact(cap).is_err() {
+        mem::forget(mem::take(buf));
+        match reserve_error::<T>(cap) {
+            AllocError::OutOfMemory => oom(Layout::array::<T>(cap).expect("capacity overflow")),
+            _ => panic!("capacity overflow"),
+        }
+    }
+}
+
+/// Whether resizing the allocation at `ptr` to `new_bytes` bytes involves an
+/// anonymous mapping, which only `raw_alloc` and `raw_dealloc` can handle.
+#[inline]
+fn involves_mapping<T>(ptr: *mut T, new_bytes: usize) -> bool {
+    #[cfg(all(unix, feature = "mmap"))]
+    {
+        mapped(ptr) || mmap::would_map(new_bytes)
+    }
+    #[cfg(not(all(unix, feature = "mmap")))]
+    {
+        let _ = new_bytes;
+        mapped(ptr)
+    }
+}
+
+/// Whether the allocation at `ptr` is an anonymous mapping rather than heap
+/// memory. Always `false` without the `mmap` feature.
+#[inline]
+fn mapped<T>(ptr: *mut T) -> bool {
+    #[cfg(all(unix, feature = "mmap"))]
+    {
+        mmap::is_mapped(ptr)
+    }
+    #[cfg(not(all(unix, feature = "mmap")))]
+    {
+        let _ = ptr;
+        false
+    }
+}
+
+/// Moves the `cap` values at `ptr` into a fresh allocation of `new_cap`
+/// values and frees the old one, for growing to or from an anonymous
+/// mapping.
+unsafe fn grow_by_copy<T>(ptr: *mut T, cap: usize, new_cap: usize, new_bytes: usize) -> *mut T {
+    redzone::check(ptr, cap);
+    let realloc = registry::begin_realloc(ptr);
+    let new_ptr = redzone::guard(raw_alloc::<T>(redzone::padded::<T>(new_cap)), new_cap);
+    ptr::copy_nonoverlapping(ptr, new_ptr, cap);
+    mark_moved(ptr, cap);
+    raw_dealloc(redzone::base(ptr), redzone::padded::<T>(cap));
+    realloc.finish(new_ptr, new_bytes);
+
+    new_ptr
+}
+
+/// Allocates room for exactly `cap` values of type `T`, from an anonymous
+/// mapping if it reaches the threshold set by `set_mmap_threshold`, and from
+/// the heap otherwise.
+#[inline]
+unsafe fn raw_alloc<T>(cap: usize) -> *mut T {
+    // Sizes too large for a layout are left for `heap_alloc` to reject.
+    #[cfg(all(unix, feature = "mmap"))]
+    if let Some(base) = Layout::array::<T>(cap).ok().and_then(|layout| mmap::map(layout)) {
+        return base as *mut T;
+    }
+
+    heap_alloc(cap)
+}
+
+/// Like `raw_alloc`, but returns `None` instead of calling `oom` if the
+/// memory cannot be allocated. The caller classifies the failure.
+#[inline]
+unsafe fn try_raw_alloc<T>(cap: usize) -> Option<*mut T> {
+    #[cfg(all(unix, feature = "mmap"))]
+    if let Ok(layout) = Layout::array::<T>(cap) {
+        match mmap::try_map(layout) {
+            Ok(Some(base)) => return Some(base as *mut T),
+            Ok(None) => {},
+            Err(_) => return None,
+        }
+    }
+
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(cap).ok()?;
+    let (base, len, granted) = decompose_vec(buf);
+    check_granted::<T>(len, 0, granted, cap);
+
+    Some(base)
+}
+
+/// Frees an allocation of `cap` values of type `T` made by `raw_alloc`.
+#[inline]
+unsafe fn raw_dealloc<T>(base: *mut T, cap: usize) {
+    #[cfg(all(unix, feature = "mmap"))]
+    if mmap::unmap(base as *mut u8) {
+        return;
+    }
+
+    heap_dealloc(base, cap)
+}
+
+/// Allocates room for exactly `cap` values of type `T`, taking it from a
+/// `Vec`.
+#[cfg(not(feature = "direct"))]
+#[inline]
+unsafe fn heap_alloc<T>(cap: usize) -> *mut T {
+    let mut buf = Vec::new();
+    reserve_or_oom(&mut buf, cap);
+    let (base, len, granted) = decompose_vec(buf);
+    check_granted::<T>(len, 0, granted, cap);
+
+    base
+}
+
+/// Allocates room for exactly `cap` values of type `T` with
+/// `std::alloc::alloc`, under the layout a `Vec` would use.
+#[cfg(feature = "direct")]
+#[inline]
+unsafe fn heap_alloc<T>(cap: usize) -> *mut T {
+    let layout = Layout::array::<T>(cap).expect("capacity overflow");
+    if layout.size() == 0 {
+        return ptr::NonNull::dangling().as_ptr();
+    }
+
+    let base = alloc::alloc(layout);
+    if base.is_null() {
+        oom(layout);
+    }
+
+    base as *mut T
+}
+
+/// Frees an allocation of `cap` values of type `T` made by `heap_alloc`.
+#[cfg(not(feature = "direct"))]
+#[inline]
+unsafe fn heap_dealloc<T>(base: *mut T, cap: usize) {
+    drop(Vec::from_raw_parts(base, 0, cap));
+}
+
+/// Frees an allocation of `cap` values of type `T` made by `heap_alloc`.
+#[cfg(feature = "direct")]
+#[inline]
+unsafe fn heap_dealloc<T>(base: *mut T, cap: usize) {
+    let layout = Layout::array::<T>(cap).expect("capacity overflow");
+    if layout.size() != 0 {
+        alloc::dealloc(base as *mut u8, layout);
+    }
+}
+
+/// Takes apart `buf` without freeing it, returning its pointer, length and
+/// capacity.
+///
+/// This is where every allocation made through a `Vec` is handed over to the
+/// caller, so the capacity returned is the one that must later be passed
+/// back to `Vec::from_raw_parts`.
+#[inline]
+fn decompose_vec<T>(buf: Vec<T>) -> (*mut T, usize, usize) {
+    let mut buf = mem::ManuallyDrop::new(buf);
+
+    (buf.as_mut_ptr(), buf.len(), buf.capacity())
+}
+
+/// Checks, in debug builds, that a `Vec` taken apart by `decompose_vec` has
+/// the expected length and exactly the capacity that was asked for, since
+/// the allocation will later be freed with that capacity.
+///
+/// Zero-sized types always get a capacity of `usize::MAX`.
+#[inline]
+fn check_granted<T>(len: usize, expected_len: usize, cap: usize, size: usize) {
+    debug_assert_eq!(len, expected_len);
+    debug_assert!(cap == size || mem::size_of::<T>() == 0,
+                  "memalloc: asked for a capacity of {}, but got {}", size, cap);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, panic, ptr, slice};
+    use std::alloc::{self, Layout};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use {allocate, reallocate, deallocate, empty};
+    use {typed_dealloc, try_typed_alloc, try_typed_realloc, try_grow_amortized, AllocError};
+    use {compiler_fence_seqcst, fence_seqcst};
+    use {realloc_floor, realloc_typed, typed_alloc, typed_realloc};
+    use {alloc_zst_aware, dealloc_zst_aware};
+    use {elements_between, grow_from_known, grow_pair, grow_to_pow2, set_len_within_cap,
+         would_grant};
+    use {read_str, write_str};
+    use {alloc_migration, realloc_layout, realloc_map, realloc_move_with};
+    use {alloc_from_iter, alloc_try_init, alloc_uninit_box, shrink_exact};
+    use {alloc_ffi_array, check_granted, checked_realloc, decompose_vec};
+    use {alloc_batch, dealloc_batch};
+    use {alloc_slice_nonnull, alloc_uninit_slice, assume_init_slice};
+    #[cfg(feature = "libc")]
+    use realloc_c;
+    #[cfg(feature = "bytes")]
+    use into_bytes_mut;
+    #[cfg(debug_assertions)]
+    use {alloc_debug_zeroed, mark_moved, MOVED_FILL, UNINIT_FILL};
+    #[cfg(feature = "stats")]
+    use {allocation_size, live_allocations, owns, typed_alloc_located};
+
+    struct DropCounter(Rc<Cell<usize>>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let ptr = empty();
+        assert!(!ptr.is_null());
+    }
+
+    #[test]
+    fn test_allocate() {
+        let buffer = unsafe { allocate(8) };
+
+        assert!(!buffer.is_null());
+
+        unsafe {
+            ptr::write(buffer.offset(0), 8);
+            ptr::write(buffer.offset(1), 4);
+            ptr::write(buffer.offset(3), 5);
+            ptr::write(buffer.offset(5), 3);
+            ptr::write(buffer.offset(7), 6);
+
+            assert_eq!(ptr::read(buffer.offset(0)), 8);
+            assert_eq!(ptr::read(buffer.offset(1)), 4);
+            assert_eq!(ptr::read(buffer.offset(3)), 5);
+            assert_eq!(ptr::read(buffer.offset(5)), 3);
+            assert_eq!(ptr::read(buffer.offset(7)), 6);
+        };
+
+        unsafe { deallocate(buffer, 8); }
+
+        // Try a large buffer
+        let buffer = unsafe { allocate(1024 * 1024) };
+        assert!(!buffer.is_null());
+
+        unsafe {
+            ptr::write(buffer.offset(1024 * 1024 - 1), 12);
+            assert_eq!(ptr::read(buffer.offset(1024 * 1024 - 1)), 12);
+        };
+
+        unsafe { deallocate(buffer, 1024 * 1024); }
+    }
+
+    #[test]
+    fn test_reallocate() {
+        let mut buffer = unsafe { allocate(8) };
+        assert!(!buffer.is_null());
+
+        buffer = unsafe { reallocate(buffer, 8, 16) };
+        assert!(!buffer.is_null());
+
+        unsafe {
+            // Put some data in the buffer
+            ptr::write(buffer.offset(0), 8);
+            ptr::write(buffer.offset(1), 4);
+            ptr::write(buffer.offset(5), 3);
+            ptr::write(buffer.offset(7), 6);
+        };
+
+        // Allocate so in-place fails.
+        unsafe { allocate(128) };
+
+        buffer = unsafe { reallocate(buffer, 16, 32) };
+        assert!(!buffer.is_null());
+
+        unsafe {
+            // Ensure the data is still there.
+            assert_eq!(ptr::read(buffer.offset(0)), 8);
+            assert_eq!(ptr::read(buffer.offset(1)), 4);
+            assert_eq!(ptr::read(buffer.offset(5)), 3);
+            assert_eq!(ptr::read(buffer.offset(7)), 6);
+        };
+    }
+
+    #[test]
+    fn test_try_typed_alloc() {
+        unsafe {
+            let buffer = try_typed_alloc::<u32>(16).unwrap();
+            ptr::write(buffer.offset(15), 7);
+            assert_eq!(ptr::read(buffer.offset(15)), 7);
+
+            let buffer = try_typed_realloc(buffer, 16, 64).unwrap();
+            assert_eq!(ptr::read(buffer.offset(15)), 7);
+            typed_dealloc(buffer, 64);
+
+            assert_eq!(try_typed_alloc::<u32>(usize::MAX / 2).unwrap_err(),
+                       AllocError::CapacityOverflow);
+        }
+    }
+
+    #[test]
+    fn test_try_typed_realloc_failure_keeps_original() {
+        unsafe {
+            let buffer = try_typed_alloc::<u64>(4).unwrap();
+            ptr::write(buffer, 42);
+
+            assert_eq!(try_typed_realloc(buffer, 4, usize::MAX / 4).unwrap_err(),
+                       AllocError::CapacityOverflow);
+
+            assert_eq!(ptr::read(buffer), 42);
+            typed_dealloc(buffer, 4);
+        }
+    }
+
+    #[test]
+    fn test_try_grow_amortized() {
+        unsafe {
+            let (mut buffer, mut cap) = try_grow_amortized::<u64>(ptr::null_mut(), 0, 1).unwrap();
+            assert_eq!(cap, 8);
+            ptr::write(buffer, 3);
+
+            for _ in 0..5 {
+                let (new_buffer, new_cap) = try_grow_amortized(buffer, cap, cap + 1).unwrap();
+                assert_eq!(new_cap, cap * 2);
+                assert_eq!(ptr::read(new_buffer), 3);
+
+                ptr::write(new_buffer.offset(new_cap as isize - 1), 9);
+                buffer = new_buffer;
+                cap = new_cap;
+            }
+
+            // A large enough requirement wins over doubling.
+            let (buffer, cap) = try_grow_amortized(buffer, cap, cap * 10).unwrap();
+            assert_eq!(cap, 2560);
+            typed_dealloc(buffer, cap);
+
+            let (buffer, cap) = try_grow_amortized::<u8>(ptr::null_mut(), 0, 1).unwrap();
+            assert_eq!(cap, 64);
+            typed_dealloc(buffer, cap);
+        }
+    }
+
+    #[test]
+    fn test_fences() {
+        unsafe {
+            let buffer = allocate(16);
+            ptr::write_bytes(buffer, 0xAA, 16);
+
+            ptr::write_bytes(buffer, 0, 16);
+            compiler_fence_seqcst();
+            fence_seqcst();
+
+            assert_eq!(ptr::read(buffer.offset(15)), 0);
+            deallocate(buffer, 16);
+        }
+    }
+
+    #[test]
+    fn test_realloc_typed() {
+        unsafe {
+            // Different alignment, so the buffer has to move.
+            let bytes = allocate(16);
+            for i in 0..16 {
+                ptr::write(bytes.offset(i), i as u8);
+            }
+
+            let words = realloc_typed::<u8, u32>(bytes, 16, 4);
+            assert_eq!(words as usize % mem::align_of::<u32>(), 0);
+            assert_eq!(ptr::read(words), u32::from_ne_bytes([0, 1, 2, 3]));
+            assert_eq!(ptr::read(words.offset(3)), u32::from_ne_bytes([12, 13, 14, 15]));
+
+            // Same alignment, reallocated in place where possible.
+            let words = realloc_typed::<u32, i32>(words, 4, 8);
+            assert_eq!(ptr::read(words.offset(3)), i32::from_ne_bytes([12, 13, 14, 15]));
+
+            typed_dealloc(words, 8);
+        }
+    }
+
+    #[test]
+    fn test_alloc_uninit_slice() {
+        unsafe {
+            let slice = alloc_uninit_slice::<u16>(4);
+            assert_eq!(slice.len(), 4);
+
+            for (i, elem) in slice.iter_mut().enumerate() {
+                elem.write(i as u16 * 10);
+            }
+
+            let init = assume_init_slice(slice);
+            assert_eq!(init, &[0, 10, 20, 30]);
+
+            typed_dealloc(init.as_mut_ptr(), 4);
+        }
+    }
+
+    #[test]
+    fn test_realloc_floor() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(64);
+            ptr::write(buffer.offset(3), 12);
+
+            // Shrinking below the floor stops at the floor.
+            let (buffer, cap) = realloc_floor(buffer, 64, 2, 16);
+            assert_eq!(cap, 16);
+            assert_eq!(ptr::read(buffer.offset(3)), 12);
+
+            // Growing is exact.
+            let (buffer, cap) = realloc_floor(buffer, cap, 100, 16);
+            assert_eq!(cap, 100);
+            assert_eq!(ptr::read(buffer.offset(3)), 12);
+
+            typed_dealloc(buffer, cap);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_debug_fill_on_alloc() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(4);
+            let bytes = buffer as *const u8;
+            for i in 0..16 {
+                assert_eq!(ptr::read(bytes.offset(i)), UNINIT_FILL);
+            }
+            typed_dealloc(buffer, 4);
+
+            let buffer = try_typed_alloc::<u8>(8).unwrap();
+            assert_eq!(ptr::read(buffer), UNINIT_FILL);
+            deallocate(buffer, 8);
+        }
+    }
+
+    #[test]
+    fn test_grow_pair() {
+        unsafe {
+            let a = typed_alloc::<u8>(4);
+            let b = typed_alloc::<u64>(4);
+            ptr::write(a.offset(3), 3);
+            ptr::write(b.offset(3), 33);
+
+            let (a, b) = grow_pair(a, b, 4, 32).unwrap();
+            assert_eq!(ptr::read(a.offset(3)), 3);
+            assert_eq!(ptr::read(b.offset(3)), 33);
+
+            ptr::write(a.offset(31), 31);
+            ptr::write(b.offset(31), 331);
+
+            typed_dealloc(a, 32);
+            typed_dealloc(b, 32);
+        }
+    }
+
+    #[test]
+    fn test_elements_between() {
+        unsafe {
+            let buffer = typed_alloc::<u64>(10);
+
+            assert_eq!(elements_between(buffer, buffer.offset(10)), 10);
+            assert_eq!(elements_between(buffer.offset(2), buffer.offset(7)), 5);
+            assert_eq!(elements_between(buffer.offset(4), buffer.offset(4)), 0);
+
+            typed_dealloc(buffer, 10);
+        }
+    }
+
+    #[test]
+    fn test_realloc_layout() {
+        unsafe {
+            let old_layout = Layout::from_size_align(64, 8).unwrap();
+            let buffer = alloc::alloc(old_layout);
+            for i in 0..64 {
+                ptr::write(buffer.offset(i), i as u8);
+            }
+
+            // Same alignment, eligible for in-place reallocation.
+            let grown_layout = Layout::from_size_align(256, 8).unwrap();
+            let buffer = realloc_layout(buffer, old_layout, grown_layout);
+            assert_eq!(buffer as usize % 8, 0);
+            assert_eq!(ptr::read(buffer.offset(63)), 63);
+
+            // Changed alignment, which has to move.
+            let aligned_layout = Layout::from_size_align(32, 128).unwrap();
+            let buffer = realloc_layout(buffer, grown_layout, aligned_layout);
+            assert_eq!(buffer as usize % 128, 0);
+            for i in 0..32 {
+                assert_eq!(ptr::read(buffer.offset(i)), i as u8);
+            }
+
+            alloc::dealloc(buffer, aligned_layout);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "stats", not(feature = "redzone")))]
+    fn test_realloc_layout_moves_record() {
+        let aligned = Layout::from_size_align(128, 64).unwrap();
+
+        unsafe {
+            let buffer = typed_alloc::<u8>(64);
+            let moved = realloc_layout(buffer, Layout::array::<u8>(64).unwrap(), aligned);
+            assert_eq!(allocation_size(moved), Some(128));
+
+            let grown = realloc_layout(moved, aligned, Layout::array::<u8>(256).unwrap());
+            assert_eq!(allocation_size(grown), Some(256));
+            typed_dealloc(grown, 256);
+        }
+    }
+
+    #[test]
+    fn test_grow_from_known() {
+        unsafe {
+            let known = typed_alloc::<u32>(4);
+            let realloced = typed_alloc::<u32>(4);
+            for i in 0..4 {
+                ptr::write(known.offset(i), i as u32 * 3);
+                ptr::write(realloced.offset(i), i as u32 * 3);
+            }
+
+            let known = grow_from_known(known, 4, 1000);
+            let realloced = typed_realloc(realloced, 4, 1000);
+            for i in 0..1000 {
+                ptr::write(known.offset(i), i as u32);
+                ptr::write(realloced.offset(i), i as u32);
+            }
+
+            for i in 0..1000 {
+                assert_eq!(ptr::read(known.offset(i)), ptr::read(realloced.offset(i)));
+            }
+
+            typed_dealloc(known, 1000);
+            typed_dealloc(realloced, 1000);
+        }
+    }
+
+    #[test]
+    fn test_write_str() {
+        unsafe {
+            let buffer = allocate(32);
+
+            let mut len = write_str(buffer, 0, "hello, ");
+            assert_eq!(len, 7);
+            len += write_str(buffer, len, "wörld");
+            assert_eq!(len, 13);
+
+            assert_eq!(read_str(buffer, 0, len), "hello, wörld");
+            assert_eq!(read_str(buffer, 7, 6), "wörld");
+
+            deallocate(buffer, 32);
+        }
+    }
+
+    #[test]
+    fn test_alloc_migration() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(8);
+            for i in 0..8 {
+                ptr::write(buffer.offset(i), i as u32);
+            }
+
+            let (old, new) = alloc_migration(buffer, 8, 16);
+            assert_eq!(old, buffer);
+            for i in 0..8 {
+                ptr::write(new.offset(i), ptr::read(old.offset(i)) * 2);
+            }
+            typed_dealloc(old, 8);
+
+            for i in 0..8 {
+                assert_eq!(ptr::read(new.offset(i)), i as u32 * 2);
+            }
+            typed_dealloc(new, 16);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn test_typed_alloc_located() {
+        unsafe {
+            let line = line!() + 1;
+            let leaked = typed_alloc_located::<u32>(6);
+            let freed = typed_alloc_located::<u32>(6);
+            let unlocated = typed_alloc::<u32>(6);
+            typed_dealloc(freed, 6);
+
+            let live = live_allocations();
+            assert_eq!(live.len(), 1);
+
+            let (addr, bytes, location) = live[0];
+            assert_eq!(addr, leaked as usize);
+            assert_eq!(bytes, 24);
+            assert_eq!(location.file(), file!());
+            assert_eq!(location.line(), line);
+
+            // The location follows the allocation when it moves.
+            let leaked = typed_realloc(leaked, 6, 600);
+            assert_eq!(live_allocations()[0].0, leaked as usize);
+            assert_eq!(live_allocations()[0].1, 2400);
+
+            typed_dealloc(leaked, 600);
+            typed_dealloc(unlocated, 6);
+            assert!(live_allocations().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_realloc_move_with() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(5);
+            for i in 0..5 {
+                ptr::write(buffer.offset(i), i as u32 * 10);
+            }
+
+            let mut moved = Vec::new();
+            let buffer = realloc_move_with(buffer, 5, 3, |old, new| {
+                let value = ptr::read(old);
+                moved.push(value);
+                ptr::write(new, value + 1);
+            });
+
+            assert_eq!(moved, [0, 10, 20]);
+            for i in 0..3 {
+                assert_eq!(ptr::read(buffer.offset(i)), i as u32 * 10 + 1);
+            }
+
+            typed_dealloc(buffer, 3);
+        }
+    }
+
+    #[test]
+    fn test_alloc_uninit_box() {
+        let mut boxed = unsafe { alloc_uninit_box::<String>(3) };
+        assert_eq!(boxed.len(), 3);
+
+        for (i, elem) in boxed.iter_mut().enumerate() {
+            elem.write(i.to_string());
+        }
+
+        let boxed = unsafe { boxed.assume_init() };
+        assert_eq!(&*boxed, &["0", "1", "2"]);
+    }
+
+    #[test]
+    fn test_alloc_from_iter() {
+        unsafe {
+            let (buffer, len) = alloc_from_iter((0..10).map(|i| i * i));
+            assert_eq!(len, 10);
+            assert_eq!(slice::from_raw_parts(buffer, len), &[0, 1, 4, 9, 16, 25, 36, 49, 64, 81]);
+            typed_dealloc(buffer, 10);
+
+            let (_, len) = alloc_from_iter(Vec::<u8>::new().into_iter());
+            assert_eq!(len, 0);
+        }
+    }
+
+    #[test]
+    fn test_alloc_from_iter_panic() {
+        let drops = Rc::new(Cell::new(0));
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| unsafe {
+            alloc_from_iter((0..5).map(|i| {
+                if i == 3 {
+                    panic!("iterator failed");
+                }
+                DropCounter(drops.clone())
+            }))
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    #[cfg(all(feature = "stats", debug_assertions))]
+    fn test_dealloc_size_verification() {
+        unsafe {
+            let words = typed_alloc::<u32>(4);
+            assert_eq!(allocation_size(words), Some(16));
+            typed_dealloc(words, 4);
+            assert_eq!(allocation_size(words), None);
+
+            // Freeing 8 bytes as a single `u32` is a type-confused free,
+            // which panics before anything is freed.
+            let bytes = allocate(8);
+            let result = panic::catch_unwind(|| typed_dealloc(bytes as *mut u32, 1));
+            assert!(result.is_err());
+            assert_eq!(allocation_size(bytes), Some(8));
+
+            deallocate(bytes, 8);
+        }
+    }
+
+    #[test]
+    fn test_alloc_try_init() {
+        unsafe {
+            let buffer = alloc_try_init::<u32, (), _>(4, |i| Ok(i as u32 + 1)).unwrap();
+            assert_eq!(slice::from_raw_parts(buffer, 4), &[1, 2, 3, 4]);
+            typed_dealloc(buffer, 4);
+        }
+    }
+
+    #[test]
+    fn test_alloc_try_init_error() {
+        let drops = Rc::new(Cell::new(0));
+
+        let result = unsafe {
+            alloc_try_init(10, |i| {
+                if i == 5 {
+                    Err("init failed")
+                } else {
+                    Ok(DropCounter(drops.clone()))
+                }
+            })
+        };
+
+        assert_eq!(result.err(), Some("init failed"));
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn test_shrink_exact() {
+        unsafe {
+            let buffer = typed_alloc::<u16>(1000);
+            for i in 0..10 {
+                ptr::write(buffer.offset(i), i as u16);
+            }
+
+            let (buffer, cap) = shrink_exact(buffer, 10, 1000);
+            assert_eq!(cap, 10);
+            assert_eq!(slice::from_raw_parts(buffer, 10), &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+            #[cfg(feature = "stats")]
+            assert_eq!(allocation_size(buffer), Some(20));
+
+            typed_dealloc(buffer, cap);
+
+            let (_, cap) = shrink_exact(typed_alloc::<u16>(8), 0, 8);
+            assert_eq!(cap, 0);
+        }
+    }
+
+    #[test]
+    fn test_shrink_runs_no_destructors() {
+        struct DropCounter<'a>(&'a Cell<usize>, u32);
+
+        impl<'a> Drop for DropCounter<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Cell::new(0);
+        unsafe {
+            let buffer = typed_alloc::<DropCounter>(8);
+            for i in 0..8 {
+                ptr::write(buffer.add(i), DropCounter(&drops, i as u32));
+            }
+
+            // The values past the new size are the caller's to drop.
+            for i in 4..8 {
+                ptr::drop_in_place(buffer.add(i));
+            }
+            assert_eq!(drops.get(), 4);
+
+            let buffer = typed_realloc(buffer, 8, 4);
+            assert_eq!(drops.get(), 4);
+            assert_eq!((*buffer.add(3)).1, 3);
+
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(buffer, 4));
+            typed_dealloc(buffer, 4);
+            assert_eq!(drops.get(), 8);
+        }
+    }
+
+    // With or without the `direct` feature, the typed functions hand out
+    // exactly the allocation a `Vec` of the same capacity owns, so the two
+    // can free each other's memory.
+    #[test]
+    #[cfg(not(all(feature = "redzone", debug_assertions)))]
+    fn test_interchangeable_with_vec() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(16);
+            #[cfg(debug_assertions)]
+            assert!(slice::from_raw_parts(buffer as *const u8, 64).iter().all(|&b| b == UNINIT_FILL));
+            for i in 0..16 {
+                ptr::write(buffer.add(i), i as u32);
+            }
+
+            let vec = Vec::from_raw_parts(buffer, 16, 16);
+            assert_eq!(vec, (0..16).collect::<Vec<u32>>());
+            drop(vec);
+
+            let (buffer, _, cap) = decompose_vec(vec![7u64; 9]);
+            assert_eq!(ptr::read(buffer.add(8)), 7);
+            typed_dealloc(buffer, cap);
+
+            let unit = alloc_zst_aware::<()>(3);
+            dealloc_zst_aware(unit, 3);
+        }
+    }
+
+    #[test]
+    fn test_realloc_map() {
+        unsafe {
+            let buffer = typed_alloc::<u8>(8);
+            for i in 0..6 {
+                ptr::write(buffer.add(i), 250 + i as u8);
+            }
+
+            let (widened, cap) = realloc_map(buffer, 6, 8, |byte: u8| byte as u32 * 2);
+            assert_eq!(cap, 6);
+            assert_eq!(slice::from_raw_parts(widened, 6), [500, 502, 504, 506, 508, 510]);
+            typed_dealloc(widened, cap);
+        }
+    }
+
+    #[test]
+    fn test_realloc_map_panic() {
+        let shared = Rc::new(());
+        let converted = Cell::new(0);
+
+        unsafe {
+            let buffer = typed_alloc::<Rc<()>>(5);
+            for i in 0..5 {
+                ptr::write(buffer.add(i), shared.clone());
+            }
+
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                realloc_map(buffer, 5, 5, |rc: Rc<()>| {
+                    if converted.get() == 2 {
+                        panic!("conversion failed");
+                    }
+                    converted.set(converted.get() + 1);
+                    (rc, 0u8)
+                })
+            }));
+            assert!(result.is_err());
+        }
+
+        // Every clone was dropped: the converted ones, the one being
+        // converted and the unread ones.
+        assert_eq!(converted.get(), 2);
+        assert_eq!(Rc::strong_count(&shared), 1);
+    }
+
+    // A relocated buffer's old memory is freed, so the sentinel itself can't
+    // be read back through the stale pointer without undefined behavior.
+    // `mark_moved` is checked on a live buffer instead; `relocated_to` is
+    // tested in tests/registry.rs, away from other threads reusing the
+    // address.
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_moved_sentinel() {
+        unsafe {
+            let buffer = typed_alloc::<u8>(64);
+            ptr::write_bytes(buffer, 0, 64);
+            mark_moved(buffer, 64);
+            assert!(slice::from_raw_parts(buffer, 16).iter().all(|&b| b == MOVED_FILL));
+            assert!(slice::from_raw_parts(buffer.add(16), 48).iter().all(|&b| b == 0));
+
+            // Shrinking always relocates.
+            let moved = typed_realloc(buffer, 64, 8);
+            assert_ne!(moved, buffer);
+            typed_dealloc(moved, 8);
+        }
+    }
+
+    #[test]
+    fn test_alloc_slice_nonnull() {
+        unsafe {
+            let buffer = alloc_slice_nonnull::<u64>(10);
+            assert!(buffer.len() >= 10);
+            assert_eq!(buffer.as_ptr() as *mut u64 as usize % mem::align_of::<u64>(), 0);
+
+            let base = buffer.as_ptr() as *mut u64;
+            for i in 0..buffer.len() {
+                ptr::write(base.add(i), i as u64);
+            }
+            assert_eq!(ptr::read(base.add(buffer.len() - 1)), buffer.len() as u64 - 1);
+
+            typed_dealloc(base, buffer.len());
+        }
+    }
+
+    #[test]
+    fn test_would_grant() {
+        for &size in &[1, 7, 64, 1000, 4096] {
+            assert!(would_grant::<u8>(size) >= size);
+            assert!(would_grant::<u64>(size) >= size);
+        }
+        assert_eq!(would_grant::<()>(3), usize::MAX);
+
+        // The prediction matches what `Vec` really grants.
+        let granted = Vec::<u32>::with_capacity(37).capacity();
+        assert_eq!(would_grant::<u32>(37), granted);
+    }
+
+    #[test]
+    fn test_grow_to_pow2() {
+        unsafe {
+            let (buffer, cap) = grow_to_pow2::<u16>(ptr::null_mut(), 0, 0);
+            assert_eq!(cap, 1);
+            ptr::write(buffer, 7);
+
+            let (buffer, cap) = grow_to_pow2(buffer, cap, 100);
+            assert_eq!(cap, 128);
+            assert_eq!(ptr::read(buffer), 7);
+
+            let (buffer, cap) = grow_to_pow2(buffer, cap, 128);
+            assert_eq!(cap, 128);
+            typed_dealloc(buffer, cap);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_grow_to_pow2_overflow() {
+        unsafe {
+            grow_to_pow2::<u8>(ptr::null_mut(), 0, usize::MAX / 2 + 2);
+        }
+    }
+
+    #[test]
+    fn test_set_len_within_cap() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(16);
+            for len in [0, 4, 16, 1] {
+                assert_eq!(set_len_within_cap(buffer, 16, len), buffer);
+            }
+
+            // The allocation is untouched, still recorded at its full size.
+            #[cfg(feature = "stats")]
+            assert_eq!(allocation_size(buffer), Some(64));
+            typed_dealloc(buffer, 16);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the capacity")]
+    #[cfg(debug_assertions)]
+    fn test_set_len_beyond_cap() {
+        unsafe {
+            let buffer = typed_alloc::<u8>(8);
+            set_len_within_cap(buffer, 8, 9);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn test_owns() {
+        unsafe {
+            let ours = typed_alloc::<u32>(4);
+            let foreign = Box::into_raw(Box::new(0u32));
+
+            assert!(owns(ours as *const u8));
+            assert!(!owns(foreign as *const u8));
+            assert!(!owns(ours.add(1) as *const u8));
+
+            typed_dealloc(ours, 4);
+            assert!(!owns(ours as *const u8));
+            drop(Box::from_raw(foreign));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "bytes")]
+    fn test_into_bytes_mut() {
+        let mut bytes = unsafe {
+            let buffer = allocate(32);
+            let len = write_str(buffer, 0, "hello");
+            let bytes = into_bytes_mut(buffer, len, 32);
+            assert_eq!(bytes.as_ptr(), buffer as *const u8);
+            bytes
+        };
+
+        assert_eq!(&bytes[..], b"hello");
+        assert!(bytes.capacity() >= 32);
+
+        bytes.extend_from_slice(b", world");
+        assert_eq!(&bytes.freeze()[..], b"hello, world");
+    }
+
+    #[test]
+    #[cfg(feature = "bytes")]
+    fn test_into_bytes_mut_empty() {
+        let mut bytes = unsafe { into_bytes_mut(ptr::NonNull::dangling().as_ptr(), 0, 0) };
+        assert!(bytes.is_empty());
+
+        bytes.extend_from_slice(b"grown");
+        assert_eq!(&bytes[..], b"grown");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_alloc_debug_zeroed() {
+        unsafe {
+            let buffer = alloc_debug_zeroed::<u64>(16);
+            assert!(slice::from_raw_parts(buffer, 16).iter().all(|&x| x == 0));
+
+            typed_dealloc(buffer, 16);
+        }
+    }
+
+    #[test]
+    fn test_alloc_batch() {
+        unsafe {
+            let batch = alloc_batch::<u32>(10, 4);
+            assert_eq!(batch.len(), 4);
+
+            for (n, &buffer) in batch.iter().enumerate() {
+                for i in 0..10 {
+                    ptr::write(buffer.add(i), (n * 10 + i) as u32);
+                }
+            }
+            for (n, &buffer) in batch.iter().enumerate() {
+                let expected = (0..10).map(|i| (n * 10 + i) as u32).collect::<Vec<_>>();
+                assert_eq!(slice::from_raw_parts(buffer, 10), &expected[..]);
+            }
+
+            dealloc_batch(batch, 10);
+
+            let empty = alloc_batch::<u32>(10, 0);
+            assert!(empty.is_empty());
+            dealloc_batch(empty, 10);
+        }
+    }
+
+    #[test]
+    fn test_checked_realloc() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(4);
+            ptr::write(buffer, 7);
+
+            assert_eq!(checked_realloc(buffer, 8, Some(4), 16), Err(AllocError::SizeMismatch));
+            assert_eq!(ptr::read(buffer), 7);
+
+            let buffer = checked_realloc(buffer, 4, Some(4), 16).unwrap();
+            assert_eq!(ptr::read(buffer), 7);
+
+            let buffer = checked_realloc(buffer, 16, None, 32).unwrap();
+            assert_eq!(ptr::read(buffer), 7);
+
+            typed_dealloc(buffer, 32);
+        }
+    }
+
+    #[test]
+    fn test_decompose_vec() {
+        let mut buf = Vec::with_capacity(10);
+        buf.extend_from_slice(&[1u32, 2, 3]);
+        let addr = buf.as_ptr();
+
+        let (ptr, len, cap) = decompose_vec(buf);
+        assert_eq!((ptr as *const u32, len, cap), (addr, 3, 10));
+        check_granted::<u32>(len, 3, cap, 10);
+        drop(unsafe { Vec::from_raw_parts(ptr, len, cap) });
+
+        let (_, len, cap) = decompose_vec(Vec::<()>::with_capacity(10));
+        assert_eq!((len, cap), (0, usize::MAX));
+        check_granted::<()>(len, 0, cap, 10);
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_check_granted_mismatch() {
+        check_granted::<u32>(0, 0, 16, 10);
+    }
+
+    // In debug builds, every function here checks the capacity it was
+    // granted against the capacity it asked for.
+    fn check_capacities<T>() {
+        unsafe {
+            for &size in &[1, 3, 8, 100, 4096] {
+                let ptr = typed_alloc::<T>(size);
+                let ptr = typed_realloc(ptr, size, size * 2);
+                let ptr = grow_from_known(ptr, size * 2, size * 3);
+                let ptr = typed_realloc(ptr, size * 3, size);
+                let ptr = try_typed_realloc(ptr, size, size * 4).unwrap();
+                typed_dealloc(ptr, size * 4);
+
+                let ptr = try_typed_alloc::<T>(size).unwrap();
+                typed_dealloc(ptr, size);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "memalloc does not support zero-sized types")]
+    fn test_typed_alloc_rejects_zst() {
+        unsafe { typed_alloc::<()>(4) };
+    }
+
+    #[test]
+    fn test_alloc_zst_aware() {
+        unsafe {
+            let unit = alloc_zst_aware::<()>(10);
+            assert_eq!(unit, ptr::NonNull::dangling().as_ptr());
+            dealloc_zst_aware(unit, 10);
+
+            let buffer = alloc_zst_aware::<u16>(10);
+            ptr::write(buffer.add(9), 9);
+            assert_eq!(ptr::read(buffer.add(9)), 9);
+            dealloc_zst_aware(buffer, 10);
+
+            let empty = alloc_zst_aware::<u16>(0);
+            assert_eq!(empty, ptr::NonNull::dangling().as_ptr());
+            dealloc_zst_aware(empty, 0);
+        }
+    }
+
+    #[test]
+    fn test_capacity_invariants() {
+        check_capacities::<u8>();
+        check_capacities::<u64>();
+        check_capacities::<[u8; 3]>();
+    }
+
+    #[test]
+    fn test_alloc_ffi_array() {
+        #[repr(C)]
+        struct Header {
+            tag: u8,
+            len: u32,
+            flags: u16,
+        }
+
+        unsafe {
+            let array = alloc_ffi_array::<Header>(4);
+            for i in 0..4 {
+                ptr::write(array.add(i), Header { tag: i as u8, len: 10 * i as u32, flags: 0 });
+            }
+
+            // C indexes the array by byte offsets of `sizeof(struct header)`.
+            assert_eq!(mem::size_of::<Header>(), 12);
+            let base = array as usize;
+            for i in 0..4 {
+                let element = (base + i * mem::size_of::<Header>()) as *const Header;
+                assert_eq!(element, array.add(i) as *const Header);
+                assert_eq!(((*element).tag, (*element).len, (*element).flags),
+                           (i as u8, 10 * i as u32, 0));
+            }
+
+            typed_dealloc(array, 4);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "libc")]
+    fn test_realloc_c() {
+        use libc;
+
+        unsafe {
+            let buffer = libc::malloc(16) as *mut u8;
+            assert!(!buffer.is_null());
+            for i in 0..16 {
+                ptr::write(buffer.add(i), i as u8);
+            }
+
+            let buffer = realloc_c(buffer, 16, 4096);
+            assert_eq!(slice::from_raw_parts(buffer, 16), &(0..16).collect::<Vec<u8>>()[..]);
+            ptr::write_bytes(buffer.add(16), 0xFF, 4080);
+
+            libc::free(buffer as *mut libc::c_void);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Rendering of the `stats` registry in the Prometheus text exposition
+//! format, enabled by the `metrics` feature.
+
+use std::fmt::Write;
+
+use registry;
+use {live_bytes, size_histogram};
+
+/// Renders the process's allocation statistics in the Prometheus text
+/// exposition format, ready to be served to a scraper.
+///
+/// The metrics, which will keep their names and meaning, are:
+///
+/// - `memalloc_live_bytes`, a gauge of the bytes in live allocations.
+/// - `memalloc_live_allocations`, a gauge of the number of live allocations.
+/// - `memalloc_allocations_total`, a counter of fresh allocations made.
+/// - `memalloc_reallocations_total`, a counter of reallocations, labelled
+///   with `result="moved"` or `result="in_place"`.
+/// - `memalloc_allocation_size_bytes`, a histogram of the sizes of live
+///   allocations, with power-of-two buckets.
+///
+/// Like the rest of the registry, the statistics cover the allocations of
+/// every thread.
+///
+/// Only available with the `metrics` feature.
+pub fn prometheus_metrics() -> String {
+    let counters = registry::counters();
+    let histogram = size_histogram();
+    let live = histogram.iter().map(|&(_, count)| count).sum::<usize>();
+
+    let mut out = String::new();
+    metric(&mut out, "memalloc_live_bytes", "gauge", "Bytes in live allocations.");
+    let _ = writeln!(out, "memalloc_live_bytes {}", live_bytes());
+
+    metric(&mut out, "memalloc_live_allocations", "gauge", "Number of live allocations.");
+    let _ = writeln!(out, "memalloc_live_allocations {}", live);
+
+    metric(&mut out, "memalloc_allocations_total", "counter", "Fresh allocations made.");
+    let _ = writeln!(out, "memalloc_allocations_total {}", counters.allocations);
+
+    metric(&mut out, "memalloc_reallocations_total", "counter",
+           "Reallocations, by whether the allocation moved.");
+    let _ = writeln!(out, "memalloc_reallocations_total{{result=\"moved\"}} {}",
+                     counters.reallocs_moved);
+    let _ = writeln!(out, "memalloc_reallocations_total{{result=\"in_place\"}} {}",
+                     counters.reallocs_in_place);
+
+    metric(&mut out, "memalloc_allocation_size_bytes", "histogram",
+           "Sizes in bytes of live allocations.");
+    let largest = histogram.last().map_or(1, |&(bytes, _)| bytes.next_power_of_two());
+    let (mut bound, mut below) = (1, 0);
+    let mut sizes = histogram.iter().peekable();
+    loop {
+        while let Some(&&(bytes, count)) = sizes.peek() {
+            if bytes > bound {
+                break;
+            }
+            below += count;
+            sizes.next();
+        }
+        let _ = writeln!(out, "memalloc_allocation_size_bytes_bucket{{le=\"{}\"}} {}", bound, below);
+
+        if bound >= largest {
+            break;
+        }
+        bound *= 2;
+    }
+    let _ = writeln!(out, "memalloc_allocation_size_bytes_bucket{{le=\"+Inf\"}} {}", live);
+    let _ = writeln!(out, "memalloc_allocation_size_bytes_sum {}", live_bytes());
+    let _ = writeln!(out, "memalloc_allocation_size_bytes_count {}", live);
+
+    out
+}
+
+/// Writes the `HELP` and `TYPE` lines introducing a metric.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_metrics;
+
+    // The values are process-wide, so they are checked in tests/registry.rs,
+    // away from the other tests' allocations.
+    #[test]
+    fn test_prometheus_metrics() {
+        let metrics = prometheus_metrics();
+        for name in &["memalloc_live_bytes", "memalloc_live_allocations",
+                      "memalloc_allocations_total", "memalloc_reallocations_total",
+                      "memalloc_allocation_size_bytes"] {
+            assert!(metrics.contains(&format!("# TYPE {} ", name)), "{}", name);
+        }
+        assert!(metrics.lines().any(|line| {
+            line.starts_with("memalloc_allocation_size_bytes_bucket{le=\"+Inf\"} ")
+        }));
+    }
+}
@@ -0,0 +1,131 @@
+//! Backing large allocations with anonymous mappings instead of the heap,
+//! enabled on unix by the `mmap` feature.
+//!
+//! Once `set_mmap_threshold` is called, allocations of at least the
+//! threshold are mapped with `mmap` and unmapped with `munmap`. Every live
+//! mapping is recorded by address along with its length, so freeing can
+//! tell which backing an allocation came from.
+
+use libc;
+
+use std::alloc::Layout;
+use std::collections::BTreeMap;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use oom::oom;
+use {page_size, AllocError};
+
+static THRESHOLD: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The length in bytes of every live mapping, by address.
+static MAPPED: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// The number of live mappings, so that frees can skip the lock when there
+/// are none.
+static MAPPINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the size in bytes from which the typed functions, such as
+/// `typed_alloc` and `typed_realloc`, take memory from an anonymous mapping
+/// rather than from the heap.
+///
+/// Huge buffers then go straight to the operating system and back, without
+/// fragmenting the heap, while small ones stay on it. Growing or shrinking
+/// across the threshold copies the values to the other backing. The
+/// threshold applies to the size in bytes, after multiplying by the size of
+/// the element type, and mappings are rounded up to whole pages.
+///
+/// The default is `usize::MAX`, which maps nothing. Changing it only affects
+/// allocations made afterwards: memory already mapped is still unmapped when
+/// freed.
+///
+/// Every function of the crate can resize and free mapped memory. Memory
+/// handed to a foreign owner, by `alloc_uninit_box` or `into_bytes_mut`, is
+/// never left mapped, since the owner frees it through the global
+/// allocator.
+///
+/// Only available on unix with the `mmap` feature.
+pub fn set_mmap_threshold(bytes: usize) {
+    THRESHOLD.store(bytes, Ordering::Relaxed);
+}
+
+/// Returns the threshold set by `set_mmap_threshold`.
+///
+/// Only available on unix with the `mmap` feature.
+pub fn mmap_threshold() -> usize {
+    THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Returns whether `ptr` points into memory taken from an anonymous mapping
+/// because of `set_mmap_threshold`.
+///
+/// Only available on unix with the `mmap` feature.
+pub fn is_mapped<T>(ptr: *const T) -> bool {
+    if MAPPINGS.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+
+    let addr = ptr as usize;
+    lock().range(..=addr).next_back().is_some_and(|(&base, &len)| addr < base + len)
+}
+
+/// Whether an allocation of `bytes` bytes would be mapped.
+#[inline]
+pub(crate) fn would_map(bytes: usize) -> bool {
+    let threshold = THRESHOLD.load(Ordering::Relaxed);
+
+    bytes != 0 && threshold != usize::MAX && bytes >= threshold
+}
+
+/// Maps memory for `layout` if it is at least the threshold, returning
+/// `None` if it should come from the heap instead.
+///
+/// On failure, responds as configured by `set_oom_behavior`.
+pub(crate) unsafe fn map(layout: Layout) -> Option<*mut u8> {
+    match try_map(layout) {
+        Ok(mapped) => mapped,
+        Err(_) => oom(layout),
+    }
+}
+
+/// Like `map`, but returns an error if the mapping fails.
+pub(crate) unsafe fn try_map(layout: Layout) -> Result<Option<*mut u8>, AllocError> {
+    if !would_map(layout.size()) {
+        return Ok(None);
+    }
+
+    let len = layout.size().checked_next_multiple_of(page_size())
+        .ok_or(AllocError::CapacityOverflow)?;
+    let ptr = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                         libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+    if ptr == libc::MAP_FAILED {
+        return Err(AllocError::OutOfMemory);
+    }
+
+    lock().insert(ptr as usize, len);
+    MAPPINGS.fetch_add(1, Ordering::Relaxed);
+
+    Ok(Some(ptr as *mut u8))
+}
+
+/// Unmaps the mapping starting at `base`, returning `false` if `base` is
+/// not the start of one, in which case it came from the heap.
+pub(crate) unsafe fn unmap(base: *mut u8) -> bool {
+    if MAPPINGS.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+
+    let len = match lock().remove(&(base as usize)) {
+        Some(len) => len,
+        None => return false,
+    };
+    MAPPINGS.fetch_sub(1, Ordering::Relaxed);
+    libc::munmap(base as *mut libc::c_void, len);
+
+    true
+}
+
+fn lock() -> MutexGuard<'static, BTreeMap<usize, usize>> {
+    MAPPED.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,52 @@
+//! What happens when the allocator cannot provide memory.
+
+use std::alloc::{self, Layout};
+use std::sync::RwLock;
+
+/// What the infallible functions do when an allocation fails.
+///
+/// Set with `set_oom_behavior`. The fallible `try_` functions are not
+/// affected, and return `AllocError::OutOfMemory` instead.
+#[derive(Debug, Clone, Copy)]
+pub enum OomBehavior {
+    /// Report the failure and abort the process, through
+    /// `std::alloc::handle_alloc_error`. This is the default.
+    Abort,
+    /// Panic with a message naming the size of the failed request, so the
+    /// failure can be caught with `catch_unwind` at a task boundary.
+    Panic,
+    /// Call the function with the size in bytes of the failed request. It
+    /// must not return, but may reset the device, longjmp into a runtime or
+    /// panic.
+    Custom(fn(usize) -> !),
+}
+
+static BEHAVIOR: RwLock<OomBehavior> = RwLock::new(OomBehavior::Abort);
+
+/// Sets what the infallible functions do when an allocation fails, for the
+/// whole process.
+///
+/// This covers the crate's functions, whether the memory comes from a `Vec`,
+/// from the global allocator directly or from `libc`. Allocations the
+/// standard library makes on the crate's behalf, such as the `Box` behind
+/// `alloc_uninit_box`, still go through `std::alloc::handle_alloc_error`.
+///
+/// The crate still requires `std`; this hook is what lets embedders route
+/// allocation failure into their own runtime, for instance a reset handler
+/// on a target where aborting is not an option.
+pub fn set_oom_behavior(behavior: OomBehavior) {
+    *BEHAVIOR.write().unwrap_or_else(|err| err.into_inner()) = behavior;
+}
+
+/// Responds to a failure to allocate memory under `layout`, as configured by
+/// `set_oom_behavior`.
+#[cold]
+pub(crate) fn oom(layout: Layout) -> ! {
+    let behavior = *BEHAVIOR.read().unwrap_or_else(|err| err.into_inner());
+
+    match behavior {
+        OomBehavior::Abort => alloc::handle_alloc_error(layout),
+        OomBehavior::Panic => panic!("memalloc: out of memory allocating {} bytes", layout.size()),
+        OomBehavior::Custom(handler) => handler(layout.size()),
+    }
+}
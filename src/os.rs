@@ -0,0 +1,493 @@
+#[cfg(feature = "libc")]
+use libc;
+
+#[cfg(all(unix, feature = "libc"))]
+use std::io;
+use std::ptr;
+use std::sync::OnceLock;
+
+use {alloc_aligned, allocate, dealloc_aligned};
+#[cfg(all(unix, feature = "libc"))]
+use {try_typed_alloc, AllocError};
+
+/// The size of a transparent huge page on the common architectures.
+const HUGE_PAGE: usize = 2 * 1024 * 1024;
+
+/// Returns a page-aligned pointer to `size` bytes of memory, hinting to the
+/// operating system that it should be backed by transparent huge pages.
+///
+/// The hint is only given on Linux with the `hugepage` feature enabled, and
+/// even then the kernel is free to ignore it. Elsewhere this is a plain
+/// page-aligned allocation. Allocations of at least 2 MiB are aligned to
+/// 2 MiB, since only whole aligned huge pages can back them, and every
+/// allocation is padded to a whole number of pages. Free the memory with
+/// `dealloc_hugepage`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0.
+pub unsafe fn alloc_hugepage(size: usize) -> *mut u8 {
+    let size = whole_pages(size);
+    let ptr = alloc_aligned(size, hugepage_align(size));
+
+    #[cfg(all(feature = "hugepage", target_os = "linux"))]
+    libc::madvise(ptr as *mut libc::c_void, size, libc::MADV_HUGEPAGE);
+
+    ptr
+}
+
+/// Deallocates memory returned by `alloc_hugepage`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_hugepage(size)` with the same
+/// `size`.
+pub unsafe fn dealloc_hugepage(ptr: *mut u8, size: usize) {
+    let size = whole_pages(size);
+    dealloc_aligned(ptr, size, hugepage_align(size));
+}
+
+/// The alignment of `alloc_hugepage(size)`.
+#[inline]
+fn hugepage_align(size: usize) -> usize {
+    if size >= HUGE_PAGE { HUGE_PAGE } else { page_size() }
+}
+
+/// Returns a page-aligned pointer to `size` bytes of memory, asking the
+/// kernel to back it with memory from the NUMA node `node`.
+///
+/// Binding is only attempted on Linux with the `numa` feature enabled, and
+/// is a hint: if the kernel doesn't support NUMA policies, or the node
+/// doesn't exist, this is a plain page-aligned allocation. The allocation
+/// is padded to a whole number of pages, so binding it never affects
+/// neighbouring memory. Free the memory with `dealloc_on_node`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0.
+pub unsafe fn alloc_on_node(size: usize, node: u32) -> *mut u8 {
+    let size = whole_pages(size);
+    let ptr = alloc_aligned(size, page_size());
+
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    {
+        // Not in `libc`: moves pages already faulted in, which the debug fill
+        // or an earlier user of the heap memory has done, to the node.
+        const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+
+        // The node mask covers the first 64 nodes, in as many words as that
+        // takes: two on 32-bit targets. The kernel reads one bit less than
+        // `maxnode`, hence 65.
+        if node < 64 {
+            const WORD_BITS: u32 = libc::c_ulong::BITS;
+            let mut mask = [0 as libc::c_ulong; (64 / WORD_BITS) as usize];
+            mask[(node / WORD_BITS) as usize] = 1 << (node % WORD_BITS);
+            libc::syscall(libc::SYS_mbind, ptr, size, libc::MPOL_BIND,
+                          mask.as_ptr(), 65 as libc::c_ulong, MPOL_MF_MOVE);
+        }
+    }
+    #[cfg(not(all(feature = "numa", target_os = "linux")))]
+    let _ = node;
+
+    ptr
+}
+
+/// Deallocates memory returned by `alloc_on_node`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_on_node(size, _)` with the same
+/// `size`.
+pub unsafe fn dealloc_on_node(ptr: *mut u8, size: usize) {
+    dealloc_aligned(ptr, whole_pages(size), page_size());
+}
+
+/// Returns a pointer to `size` bytes of memory, every page of which has
+/// already been written to.
+///
+/// Operating systems usually map pages lazily, on first access, which can
+/// cause latency spikes. Touching every page up front trades a slower
+/// allocation for predictable access latency afterwards. The memory is
+/// otherwise uninitialized, and is freed with `deallocate(ptr, size)`.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0.
+pub unsafe fn alloc_committed(size: usize) -> *mut u8 {
+    let ptr = allocate(size);
+    let page = page_size();
+
+    let mut offset = 0;
+    while offset < size {
+        ptr::write_volatile(ptr.add(offset), 0);
+        offset += page;
+    }
+    ptr::write_volatile(ptr.add(size - 1), 0);
+
+    ptr
+}
+
+/// Returns a page-aligned pointer to `size` bytes of memory which is locked
+/// into RAM with `mlock`, so it is never swapped out to disk.
+///
+/// This keeps secrets such as keys out of swap, and spares real-time code
+/// from page faults. Locking fails when it would exceed the process'
+/// `RLIMIT_MEMLOCK`, in which case the memory is freed again and a null
+/// pointer is returned. The allocation is padded to a whole number of
+/// pages, since locking works on whole pages and would otherwise pin the
+/// neighbouring memory too. Free the memory with `dealloc_locked`.
+///
+/// Only available on unix with the `mlock` feature.
+///
+/// On allocation failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0.
+#[cfg(all(unix, feature = "mlock"))]
+pub unsafe fn alloc_locked(size: usize) -> *mut u8 {
+    let size = whole_pages(size);
+    let ptr = alloc_aligned(size, page_size());
+
+    if libc::mlock(ptr as *const libc::c_void, size) != 0 {
+        dealloc_aligned(ptr, size, page_size());
+        return ptr::null_mut();
+    }
+
+    ptr
+}
+
+/// Unlocks and deallocates memory returned by `alloc_locked`.
+///
+/// Only available on unix with the `mlock` feature.
+///
+/// # Safety
+///
+/// `ptr` must be a non-null pointer returned by `alloc_locked(size)` with
+/// the same `size`.
+#[cfg(all(unix, feature = "mlock"))]
+pub unsafe fn dealloc_locked(ptr: *mut u8, size: usize) {
+    let size = whole_pages(size);
+    libc::munlock(ptr as *const libc::c_void, size);
+    dealloc_aligned(ptr, size, page_size());
+}
+
+/// Like `allocate`, but returns the system error number describing why the
+/// allocation failed instead of aborting the process.
+///
+/// When the allocator itself fails, this is the `errno` it reported, which
+/// is usually `ENOMEM`. `errno` is cleared before the call, so an allocator
+/// which fails without setting it reports `ENOMEM` rather than whatever an
+/// earlier call left behind; where it can't be cleared, every failure
+/// reports `ENOMEM`. Requests too large to make, or over the limit set by
+/// `set_max_allocation`, never reach the allocator and report `ENOMEM`, as
+/// `malloc` does. Free the memory with `deallocate(ptr, size)`.
+///
+/// Only available on unix with the `libc` feature.
+///
+/// # Safety
+///
+/// Behavior is undefined if `size` is 0.
+#[cfg(all(unix, feature = "libc"))]
+pub unsafe fn try_alloc_errno(size: usize) -> Result<*mut u8, i32> {
+    let cleared = clear_errno();
+
+    try_typed_alloc::<u8>(size).map_err(|err| match err {
+        AllocError::OutOfMemory if cleared => match io::Error::last_os_error().raw_os_error() {
+            Some(errno) if errno != 0 => errno,
+            _ => libc::ENOMEM,
+        },
+        _ => libc::ENOMEM,
+    })
+}
+
+/// Sets `errno` to 0, returning `false` on platforms where it can't be
+/// reached.
+#[cfg(all(unix, feature = "libc"))]
+unsafe fn clear_errno() -> bool {
+    #[cfg(any(target_os = "linux", target_os = "redox", target_os = "dragonfly"))]
+    {
+        *libc::__errno_location() = 0;
+        true
+    }
+    #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+    {
+        *libc::__error() = 0;
+        true
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "redox", target_os = "dragonfly",
+                  target_vendor = "apple", target_os = "freebsd")))]
+    {
+        false
+    }
+}
+
+/// Asks the C allocator to return freed memory at the top of the heap, and
+/// in unused pages elsewhere, to the operating system.
+///
+/// glibc keeps memory freed by large shrinks and deallocations around for
+/// reuse, so a long-running process can call this after freeing a lot of
+/// memory to reduce its footprint. It wraps `malloc_trim(0)`, so it affects
+/// the whole process heap, and only helps when the global allocator is
+/// glibc's `malloc`; with other allocators it does nothing useful.
+///
+/// Only available on Linux with glibc and the `trim` feature.
+#[cfg(all(feature = "trim", target_os = "linux", target_env = "gnu"))]
+pub fn trim_heap() {
+    unsafe { libc::malloc_trim(0) };
+}
+
+/// Returns the size of a page of memory, in bytes.
+///
+/// The operating system is asked once, with `sysconf(_SC_PAGESIZE)` on unix
+/// with the `libc` feature or `GetSystemInfo` on Windows, and the answer is
+/// cached. Where it can't be asked, this is 4096. Every page-oriented
+/// function in the crate uses this size.
+pub fn page_size() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+
+    *PAGE_SIZE.get_or_init(|| query_page_size().unwrap_or(FALLBACK_PAGE_SIZE))
+}
+
+/// Returns whether `ptr` is on a page boundary, for taking fast paths that
+/// need page-aligned memory, such as DMA or `sendfile`.
+///
+/// `alloc_hugepage`, `alloc_on_node` and `alloc_aligned` with an alignment
+/// of `page_size()` always return page-aligned memory; `alloc_committed` and
+/// the other functions may or may not.
+#[inline]
+pub fn is_page_aligned(ptr: *const u8) -> bool {
+    (ptr as usize).is_multiple_of(page_size())
+}
+
+/// The page size assumed where the operating system can't be asked.
+const FALLBACK_PAGE_SIZE: usize = 4096;
+
+#[cfg(all(unix, feature = "libc"))]
+fn query_page_size() -> Option<usize> {
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+    if size > 0 { Some(size as usize) } else { None }
+}
+
+#[cfg(windows)]
+fn query_page_size() -> Option<usize> {
+    use std::mem::MaybeUninit;
+    use std::os::raw::c_void;
+
+    // `SYSTEM_INFO`, from `sysinfoapi.h`.
+    #[repr(C)]
+    struct SystemInfo {
+        processor_architecture: u16,
+        reserved: u16,
+        page_size: u32,
+        minimum_application_address: *mut c_void,
+        maximum_application_address: *mut c_void,
+        active_processor_mask: usize,
+        number_of_processors: u32,
+        processor_type: u32,
+        allocation_granularity: u32,
+        processor_level: u16,
+        processor_revision: u16,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetSystemInfo(info: *mut SystemInfo);
+    }
+
+    let info = unsafe {
+        let mut info = MaybeUninit::<SystemInfo>::uninit();
+        GetSystemInfo(info.as_mut_ptr());
+        info.assume_init()
+    };
+
+    if info.page_size > 0 { Some(info.page_size as usize) } else { None }
+}
+
+#[cfg(not(any(all(unix, feature = "libc"), windows)))]
+fn query_page_size() -> Option<usize> {
+    None
+}
+
+/// `size` rounded up to a whole number of pages, the size of the allocation
+/// backing `alloc_hugepage(size)`, `alloc_locked(size)` and
+/// `alloc_on_node(size, _)`.
+#[inline]
+fn whole_pages(size: usize) -> usize {
+    let page = page_size();
+    size.checked_add(page - 1).expect("capacity overflow") / page * page
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use {alloc_committed, deallocate, page_size};
+    use {alloc_aligned, dealloc_aligned, is_page_aligned};
+    #[cfg(all(feature = "trim", target_os = "linux", target_env = "gnu"))]
+    use allocate;
+
+    #[test]
+    fn test_page_size() {
+        let size = page_size();
+        assert!(size.is_power_of_two());
+        assert!(size >= 4096);
+
+        // Cached, so every call agrees.
+        assert_eq!(page_size(), size);
+    }
+
+    #[test]
+    fn test_is_page_aligned() {
+        let page = page_size();
+
+        unsafe {
+            let buffer = alloc_aligned(2 * page, page);
+            assert!(is_page_aligned(buffer));
+            assert!(is_page_aligned(buffer.add(page)));
+            assert!(!is_page_aligned(buffer.add(1)));
+            assert!(!is_page_aligned(buffer.add(page / 2)));
+
+            dealloc_aligned(buffer, 2 * page, page);
+        }
+    }
+
+    #[test]
+    fn test_alloc_committed() {
+        let size = 16 * page_size() + 100;
+
+        unsafe {
+            let buffer = alloc_committed(size);
+            for i in 0..size {
+                ptr::write(buffer.add(i), i as u8);
+            }
+            for i in 0..size {
+                assert_eq!(ptr::read(buffer.add(i)), i as u8);
+            }
+
+            deallocate(buffer, size);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_alloc_hugepage() {
+        use {alloc_hugepage, dealloc_hugepage};
+
+        let size = 2 * 1024 * 1024;
+
+        unsafe {
+            let buffer = alloc_hugepage(size);
+            assert_eq!(buffer as usize % size, 0);
+
+            ptr::write_bytes(buffer, 7, size);
+            assert_eq!(ptr::read(buffer.offset(size as isize - 1)), 7);
+
+            dealloc_hugepage(buffer, size);
+
+            // Smaller allocations are only page-aligned, and padded out.
+            let small = alloc_hugepage(100);
+            assert_eq!(small as usize % page_size(), 0);
+            ptr::write_bytes(small, 7, page_size());
+            dealloc_hugepage(small, 100);
+        }
+    }
+
+    #[cfg(all(unix, feature = "mlock"))]
+    #[test]
+    fn test_alloc_locked() {
+        use {alloc_locked, dealloc_locked};
+
+        let size = page_size();
+
+        unsafe {
+            let buffer = alloc_locked(size);
+            assert!(!buffer.is_null());
+            assert_eq!(buffer as usize % size, 0);
+
+            ptr::write_bytes(buffer, 0xAB, size);
+            assert_eq!(ptr::read(buffer.add(size - 1)), 0xAB);
+
+            dealloc_locked(buffer, size);
+
+            // Partial pages are padded out, so nothing else shares them.
+            let buffer = alloc_locked(100);
+            assert!(!buffer.is_null());
+            assert_eq!(buffer as usize % size, 0);
+            ptr::write_bytes(buffer, 0xCD, size);
+            dealloc_locked(buffer, 100);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_alloc_on_node() {
+        use {alloc_on_node, dealloc_on_node};
+
+        let size = 3 * page_size() + 10;
+
+        unsafe {
+            let buffer = alloc_on_node(size, 0);
+            assert_eq!(buffer as usize % page_size(), 0);
+
+            ptr::write_bytes(buffer, 7, size);
+            assert_eq!(ptr::read(buffer.add(size - 1)), 7);
+
+            dealloc_on_node(buffer, size);
+        }
+    }
+
+    #[cfg(all(unix, feature = "libc"))]
+    #[test]
+    fn test_try_alloc_errno() {
+        use libc;
+        use {try_alloc_errno, deallocate};
+
+        unsafe {
+            assert_eq!(try_alloc_errno(isize::MAX as usize / 2), Err(libc::ENOMEM));
+            assert_eq!(try_alloc_errno(usize::MAX), Err(libc::ENOMEM));
+
+            let buffer = try_alloc_errno(64).unwrap();
+            ptr::write_bytes(buffer, 1, 64);
+            deallocate(buffer, 64);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "libc"))]
+    #[test]
+    fn test_try_alloc_errno_ignores_stale_errno() {
+        use libc;
+        use try_alloc_errno;
+
+        unsafe {
+            // Left behind by an unrelated earlier call.
+            *libc::__errno_location() = libc::EINVAL;
+            assert_eq!(try_alloc_errno(isize::MAX as usize), Err(libc::ENOMEM));
+
+            *libc::__errno_location() = libc::EINVAL;
+            assert_eq!(try_alloc_errno(usize::MAX), Err(libc::ENOMEM));
+        }
+    }
+
+    #[cfg(all(feature = "trim", target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn test_trim_heap() {
+        use trim_heap;
+
+        let size = 64 * 1024 * 1024;
+
+        unsafe {
+            let buffer = allocate(size);
+            ptr::write_bytes(buffer, 1, size);
+            deallocate(buffer, size);
+        }
+
+        trim_heap();
+    }
+}
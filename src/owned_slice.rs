@@ -0,0 +1,270 @@
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use {grow_from_known, try_grow_amortized, typed_alloc, typed_dealloc, typed_realloc, AllocError};
+
+/// A growable, owned sequence of `T`s built on this crate's allocation
+/// functions: a minimal `Vec`.
+///
+/// Unlike the raw functions, `OwnedSlice` owns its elements. Values are
+/// added with `push` and removed with `pop`, and the elements can be
+/// indexed and iterated through `Deref` and `DerefMut` to a slice. On drop,
+/// every element is dropped and then the allocation is freed.
+pub struct OwnedSlice<T> {
+    ptr: *mut T,
+    len: usize,
+    cap: usize,
+}
+
+impl<T> OwnedSlice<T> {
+    /// Creates an empty `OwnedSlice`, which does not allocate.
+    pub fn new() -> OwnedSlice<T> {
+        let cap = if mem::size_of::<T>() == 0 { usize::MAX } else { 0 };
+
+        OwnedSlice { ptr: NonNull::dangling().as_ptr(), len: 0, cap }
+    }
+
+    /// Creates an empty `OwnedSlice` with room for `cap` values.
+    ///
+    /// A capacity of 0 does not allocate. On failure, aborts the process.
+    pub fn with_capacity(cap: usize) -> OwnedSlice<T> {
+        let mut owned = OwnedSlice::new();
+        if cap != 0 && mem::size_of::<T>() != 0 {
+            owned.ptr = unsafe { typed_alloc(cap) };
+            owned.cap = cap;
+        }
+
+        owned
+    }
+
+    /// The number of elements.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of elements the allocation has room for.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Appends `value`, doubling the capacity if it is full.
+    ///
+    /// On failure, aborts the process.
+    pub fn push(&mut self, value: T) {
+        if self.len == self.cap {
+            self.grow();
+        }
+
+        unsafe { ptr::write(self.ptr.add(self.len), value) };
+        self.len += 1;
+    }
+
+    /// Removes the last element and returns it, or `None` if there are no
+    /// elements.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(unsafe { ptr::read(self.ptr.add(self.len)) })
+    }
+
+    /// Appends a copy of every value in `src`, growing the allocation with
+    /// `try_grow_amortized` if they don't fit.
+    ///
+    /// On error, nothing is appended.
+    pub(crate) fn try_extend_from_slice(&mut self, src: &[T]) -> Result<(), AllocError>
+        where T: Copy
+    {
+        let min_cap = self.len.checked_add(src.len()).ok_or(AllocError::CapacityOverflow)?;
+        if min_cap > self.cap {
+            let (ptr, cap) = unsafe { try_grow_amortized(self.ptr, self.cap, min_cap)? };
+            self.ptr = ptr;
+            self.cap = cap;
+        }
+
+        unsafe { ptr::copy_nonoverlapping(src.as_ptr(), self.ptr.add(self.len), src.len()) };
+        self.len += src.len();
+
+        Ok(())
+    }
+
+    /// A pointer to the first element, dangling but well-aligned when
+    /// nothing is allocated.
+    #[inline]
+    pub(crate) fn ptr(&self) -> *mut T {
+        self.ptr
+    }
+
+    /// Shrinks the capacity to the number of elements, freeing the
+    /// allocation if there are none.
+    ///
+    /// On failure, aborts the process.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        if mem::size_of::<T>() == 0 || self.cap == self.len {
+            return;
+        }
+
+        self.ptr = unsafe {
+            if self.len == 0 {
+                typed_dealloc(self.ptr, self.cap);
+                NonNull::dangling().as_ptr()
+            } else {
+                typed_realloc(self.ptr, self.cap, self.len)
+            }
+        };
+        self.cap = self.len;
+    }
+
+    /// Gives up ownership of the elements and the allocation, returning the
+    /// pointer, length and capacity.
+    pub(crate) fn into_raw_parts(self) -> (*mut T, usize, usize) {
+        let parts = (self.ptr, self.len, self.cap);
+        mem::forget(self);
+
+        parts
+    }
+
+    fn grow(&mut self) {
+        let cap = match self.cap {
+            0 => 4,
+            cap => cap.checked_mul(2).expect("capacity overflow"),
+        };
+
+        self.ptr = unsafe {
+            if self.cap == 0 {
+                typed_alloc(cap)
+            } else {
+                grow_from_known(self.ptr, self.cap, cap)
+            }
+        };
+        self.cap = cap;
+    }
+}
+
+/// An empty `OwnedSlice`, which does not allocate.
+impl<T> Default for OwnedSlice<T> {
+    fn default() -> OwnedSlice<T> {
+        OwnedSlice::new()
+    }
+}
+
+impl<T> Deref for OwnedSlice<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T> DerefMut for OwnedSlice<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<T> Drop for OwnedSlice<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(&mut **self as *mut [T]);
+
+            if self.cap != 0 && mem::size_of::<T>() != 0 {
+                typed_dealloc(self.ptr, self.cap);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use OwnedSlice;
+
+    struct DropCounter<'a>(&'a Cell<usize>, usize);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_push_index_pop() {
+        let mut owned = OwnedSlice::new();
+        assert_eq!(owned.capacity(), 0);
+
+        for i in 0..100 {
+            owned.push(i * 2);
+        }
+        assert_eq!(owned.len(), 100);
+        assert!(owned.capacity() >= 100);
+        assert_eq!(owned[0], 0);
+        assert_eq!(owned[99], 198);
+
+        owned[1] = 7;
+        assert_eq!(owned.iter().take(3).collect::<Vec<_>>(), [&0, &7, &4]);
+
+        assert_eq!(owned.pop(), Some(198));
+        assert_eq!(owned.len(), 99);
+    }
+
+    #[test]
+    fn test_drops_exactly_once() {
+        let drops = Cell::new(0);
+        let mut owned = OwnedSlice::with_capacity(2);
+
+        for i in 0..10 {
+            owned.push(DropCounter(&drops, i));
+        }
+        assert_eq!(drops.get(), 0);
+
+        let popped = owned.pop().unwrap();
+        assert_eq!(popped.1, 9);
+        assert_eq!(drops.get(), 0);
+        drop(popped);
+        assert_eq!(drops.get(), 1);
+
+        drop(owned);
+        assert_eq!(drops.get(), 10);
+    }
+
+    #[test]
+    fn test_pop_empty() {
+        let mut owned = OwnedSlice::<String>::new();
+        assert!(owned.pop().is_none());
+        assert!(owned.is_empty());
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        struct Unit<'a>(&'a Cell<usize>);
+        impl<'a> Drop for Unit<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Cell::new(0);
+        let mut owned = OwnedSlice::new();
+        for _ in 0..5 {
+            owned.push(Unit(&drops));
+        }
+        assert_eq!(owned.len(), 5);
+
+        drop(owned);
+        assert_eq!(drops.get(), 5);
+    }
+}
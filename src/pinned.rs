@@ -0,0 +1,95 @@
+use std::ptr::NonNull;
+
+use {alloc_zst_aware, dealloc_zst_aware};
+
+/// An allocation of `size` values of type `T` whose address never changes,
+/// freed on drop.
+///
+/// `PinnedAlloc` deliberately has no way to resize or reallocate its
+/// memory, and moving the handle doesn't move the memory, so the pointer
+/// returned by `as_ptr` is stable for the handle's whole lifetime. This
+/// makes it suitable backing storage for `Pin` and self-referential
+/// structures. Like the raw functions, `PinnedAlloc` never drops the values
+/// in the allocation.
+pub struct PinnedAlloc<T> {
+    ptr: NonNull<T>,
+    size: usize,
+}
+
+impl<T> PinnedAlloc<T> {
+    /// Allocates room for `size` values of type `T`, which are left
+    /// uninitialized.
+    ///
+    /// A size of 0 does not allocate, and neither does a zero-sized `T`. On
+    /// failure, aborts the process.
+    pub fn new(size: usize) -> PinnedAlloc<T> {
+        let ptr = unsafe { NonNull::new_unchecked(alloc_zst_aware(size)) };
+
+        PinnedAlloc { ptr, size }
+    }
+
+    /// A pointer to the start of the allocation, which stays the same until
+    /// the `PinnedAlloc` is dropped.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// The number of values the allocation has room for.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the allocation has room for no values.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl<T> Drop for PinnedAlloc<T> {
+    fn drop(&mut self) {
+        unsafe { dealloc_zst_aware(self.ptr.as_ptr(), self.size) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use {typed_alloc, typed_dealloc, typed_realloc, PinnedAlloc};
+
+    #[test]
+    fn test_address_is_stable() {
+        let pinned = PinnedAlloc::<u64>::new(8);
+        let addr = pinned.as_ptr();
+        unsafe { ptr::write(addr, 42) };
+
+        // Churn the allocator, and move the handle.
+        unsafe {
+            let mut other = typed_alloc::<u64>(1);
+            for size in 2..64 {
+                other = typed_realloc(other, size - 1, size);
+            }
+            typed_dealloc(other, 63);
+        }
+        let moved = Box::new(pinned);
+
+        assert_eq!(moved.as_ptr(), addr);
+        assert_eq!(unsafe { ptr::read(moved.as_ptr()) }, 42);
+        assert_eq!(moved.len(), 8);
+    }
+
+    #[test]
+    fn test_empty() {
+        let pinned = PinnedAlloc::<u32>::new(0);
+        assert!(pinned.is_empty());
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let pinned = PinnedAlloc::<()>::new(16);
+        assert_eq!(pinned.len(), 16);
+        unsafe { ptr::write(pinned.as_ptr(), ()) };
+    }
+}
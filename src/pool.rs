@@ -0,0 +1,242 @@
+use std::mem;
+use std::ptr::NonNull;
+
+use {typed_alloc, typed_dealloc};
+
+/// A pool of fixed-size slots for values of type `T`, allocated a slab of
+/// `slab_size` slots at a time.
+///
+/// Slots are handed out uninitialized by `alloc` and returned with `free`.
+/// Like the raw functions, the pool never drops the values in its slots.
+/// Dropping the pool frees every slab, including slots still in use.
+pub struct Pool<T> {
+    slab_size: usize,
+    slabs: Vec<*mut T>,
+    free: Vec<*mut T>,
+    hits: usize,
+    misses: usize,
+}
+
+impl<T> Pool<T> {
+    /// Creates an empty pool which allocates `slab_size` slots at a time.
+    ///
+    /// Panics if `slab_size` is 0.
+    pub fn new(slab_size: usize) -> Pool<T> {
+        assert!(slab_size != 0, "slab size must not be 0");
+
+        Pool { slab_size, slabs: Vec::new(), free: Vec::new(), hits: 0, misses: 0 }
+    }
+
+    /// Returns a pointer to a free, uninitialized slot, allocating a new
+    /// slab if there are none.
+    ///
+    /// If `T` is zero-sized, every slot is the same dangling pointer and no
+    /// slab is ever allocated. On failure, aborts the process.
+    pub fn alloc(&mut self) -> *mut T {
+        if mem::size_of::<T>() == 0 {
+            self.hits += 1;
+            return NonNull::dangling().as_ptr();
+        }
+
+        if self.free.is_empty() {
+            self.misses += 1;
+            let slab = unsafe { typed_alloc::<T>(self.slab_size) };
+            self.slabs.push(slab);
+
+            // Reversed, so that slots are handed out in address order.
+            self.free.extend((0..self.slab_size).rev().map(|i| unsafe { slab.add(i) }));
+        } else {
+            self.hits += 1;
+        }
+
+        self.free.pop().unwrap()
+    }
+
+    /// Returns a slot to the pool. The value in it is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must have been returned by `alloc` on this pool, and not
+    /// already freed.
+    pub unsafe fn free(&mut self, slot: *mut T) {
+        if mem::size_of::<T>() == 0 {
+            return;
+        }
+
+        debug_assert!(self.slab_of(slot).is_some(), "slot does not belong to this pool");
+        self.free.push(slot);
+    }
+
+    /// Frees every slab whose slots are all free, removing its slots from
+    /// the free list.
+    ///
+    /// This returns memory after a burst of frees. Slabs with any slot in
+    /// use are kept.
+    pub fn compact(&mut self) {
+        let mut free_counts = vec![0; self.slabs.len()];
+        for &slot in &self.free {
+            if let Some(slab) = self.slab_of(slot) {
+                free_counts[slab] += 1;
+            }
+        }
+
+        let slab_size = self.slab_size;
+        let empty = self.slabs.iter().zip(&free_counts)
+            .filter(|&(_, &count)| count == slab_size)
+            .map(|(&slab, _)| slab)
+            .collect::<Vec<_>>();
+
+        if empty.is_empty() {
+            return;
+        }
+
+        self.free.retain(|&slot| {
+            !empty.iter().any(|&slab| in_slab(slab, slab_size, slot))
+        });
+        self.slabs.retain(|slab| !empty.contains(slab));
+
+        for slab in empty {
+            unsafe { typed_dealloc(slab, slab_size) };
+        }
+    }
+
+    /// The number of slabs currently allocated.
+    #[inline]
+    pub fn slab_count(&self) -> usize {
+        self.slabs.len()
+    }
+
+    /// The number of free slots across all slabs.
+    #[inline]
+    pub fn free_count(&self) -> usize {
+        self.free.len()
+    }
+
+    /// The number of calls to `alloc` served from a free slot, without
+    /// allocating.
+    ///
+    /// Together with `misses`, this shows whether the slab size suits the
+    /// workload: a pool that mostly misses is allocating slabs too small to
+    /// amortize.
+    #[inline]
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of calls to `alloc` which had to allocate a new slab.
+    #[inline]
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    fn slab_of(&self, slot: *mut T) -> Option<usize> {
+        self.slabs.iter().position(|&slab| in_slab(slab, self.slab_size, slot))
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        for &slab in &self.slabs {
+            unsafe { typed_dealloc(slab, self.slab_size) };
+        }
+    }
+}
+
+#[inline]
+fn in_slab<T>(slab: *mut T, slab_size: usize, slot: *mut T) -> bool {
+    slot >= slab && slot < slab.wrapping_add(slab_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use Pool;
+
+    #[test]
+    fn test_alloc_free() {
+        let mut pool = Pool::<u64>::new(4);
+
+        let a = pool.alloc();
+        let b = pool.alloc();
+        assert_eq!(b, a.wrapping_add(1));
+        assert_eq!(pool.slab_count(), 1);
+        assert_eq!(pool.free_count(), 2);
+
+        unsafe {
+            ptr::write(a, 1);
+            ptr::write(b, 2);
+            pool.free(a);
+        }
+
+        // The freed slot is reused.
+        assert_eq!(pool.alloc(), a);
+        assert_eq!(unsafe { ptr::read(b) }, 2);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let mut pool = Pool::<()>::new(4);
+        let slots = (0..10).map(|_| pool.alloc()).collect::<Vec<_>>();
+        assert_eq!(pool.slab_count(), 0);
+
+        unsafe {
+            for &slot in &slots {
+                ptr::write(slot, ());
+                pool.free(slot);
+            }
+        }
+        pool.compact();
+        assert_eq!(pool.misses(), 0);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut pool = Pool::<u64>::new(4);
+        let slots = (0..8).map(|_| pool.alloc()).collect::<Vec<_>>();
+        assert_eq!(pool.slab_count(), 2);
+
+        unsafe {
+            // All of the second slab, and one slot of the first.
+            for &slot in &slots[3..] {
+                pool.free(slot);
+            }
+        }
+
+        pool.compact();
+        assert_eq!(pool.slab_count(), 1);
+        assert_eq!(pool.free_count(), 1);
+
+        // Compacting again keeps the partly used slab.
+        pool.compact();
+        assert_eq!(pool.slab_count(), 1);
+
+        unsafe { ptr::write(slots[0], 10) };
+        assert_eq!(pool.alloc(), slots[3]);
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let mut pool = Pool::<u32>::new(2);
+
+        // Warm the pool: the first allocation misses, the second hits.
+        let a = pool.alloc();
+        let b = pool.alloc();
+        unsafe {
+            pool.free(a);
+            pool.free(b);
+        }
+        for _ in 0..4 {
+            let slot = pool.alloc();
+            unsafe { pool.free(slot) };
+        }
+        assert_eq!(pool.misses(), 1);
+        assert_eq!(pool.hits(), 5);
+
+        pool.compact();
+        assert_eq!(pool.slab_count(), 0);
+        assert_eq!(pool.free_count(), 0);
+
+        pool.alloc();
+        assert_eq!(pool.misses(), 2);
+    }
+}
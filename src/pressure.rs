@@ -0,0 +1,69 @@
+//! Adapting how eagerly buffers grow to the memory pressure on the system.
+
+use std::sync::RwLock;
+
+use {grow_with_policy, typed_realloc, Double, Fixed, GoldenRatio, GrowthPolicy};
+
+/// How short of memory the system is, as reported by the hook installed with
+/// `set_pressure_hook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryPressure {
+    /// Memory is plentiful. This is what is assumed until a hook is set.
+    Normal,
+    /// Memory is getting short.
+    Elevated,
+    /// Memory is nearly exhausted, and every byte counts.
+    Critical,
+}
+
+static HOOK: RwLock<Option<fn() -> MemoryPressure>> = RwLock::new(None);
+
+/// Sets the function `realloc_pressure_aware` calls to learn the memory
+/// pressure, for the whole process.
+///
+/// The hook is called on every growth, so it should be cheap, for instance
+/// reading a level a monitoring thread keeps up to date.
+pub fn set_pressure_hook(hook: fn() -> MemoryPressure) {
+    *HOOK.write().unwrap_or_else(|err| err.into_inner()) = Some(hook);
+}
+
+/// Resizes the allocation referenced by `ptr` to hold at least `new_size`
+/// values of type `T`, growing more or less eagerly depending on the memory
+/// pressure, and returns the new pointer along with the new capacity.
+///
+/// When growing, the hook installed with `set_pressure_hook` is consulted:
+/// under `Normal` pressure the capacity at least doubles, like `Double`,
+/// under `Elevated` pressure it grows by at least half, like `GoldenRatio`,
+/// and under `Critical` pressure it grows to exactly `new_size`, leaving no
+/// slack. Shrinking is always exact. When `old_size` is 0 there is no
+/// allocation to grow and `ptr` is ignored.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the capacity returned by previous reallocations.
+pub unsafe fn realloc_pressure_aware<T>(ptr: *mut T, old_size: usize,
+                                        new_size: usize) -> (*mut T, usize) {
+    if new_size <= old_size {
+        return (typed_realloc(ptr, old_size, new_size), new_size);
+    }
+
+    let policy: &dyn GrowthPolicy = match pressure() {
+        MemoryPressure::Normal => &Double,
+        MemoryPressure::Elevated => &GoldenRatio,
+        MemoryPressure::Critical => &Fixed(0),
+    };
+
+    grow_with_policy(ptr, old_size, new_size, policy)
+}
+
+/// Asks the hook for the memory pressure, or assumes `Normal` without one.
+fn pressure() -> MemoryPressure {
+    let hook = *HOOK.read().unwrap_or_else(|err| err.into_inner());
+
+    hook.map_or(MemoryPressure::Normal, |hook| hook())
+}
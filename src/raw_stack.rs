@@ -0,0 +1,160 @@
+use OwnedSlice;
+
+/// A stack of values of type `T` which grows on `push` but never shrinks on
+/// its own.
+///
+/// `push` doubles the capacity when the stack is full, so pushing is
+/// amortized constant time. `pop` only moves the top value out and
+/// decrements the length, so it never reallocates: the capacity stays at its
+/// high-water mark until `shrink_to_fit` is called to reclaim it. On drop,
+/// the values left on the stack are dropped and the buffer is freed.
+pub struct RawStack<T> {
+    values: OwnedSlice<T>,
+}
+
+impl<T> RawStack<T> {
+    /// Makes an empty stack, which does not allocate until the first `push`.
+    pub fn new() -> RawStack<T> {
+        RawStack { values: OwnedSlice::new() }
+    }
+
+    /// Allocates room for `cap` values of type `T`.
+    ///
+    /// A capacity of 0 does not allocate, and a zero-sized `T` never does.
+    /// On failure, aborts the process.
+    pub fn with_capacity(cap: usize) -> RawStack<T> {
+        RawStack { values: OwnedSlice::with_capacity(cap) }
+    }
+
+    /// A pointer to the bottom of the stack.
+    ///
+    /// Dangling, but well-aligned, when the capacity is 0.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.values.ptr()
+    }
+
+    /// The number of values on the stack.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Whether the stack holds no values.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The number of values the stack has room for without growing.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// Pushes `value` on top of the stack, doubling the capacity if it is
+    /// full.
+    ///
+    /// On failure, aborts the process.
+    pub fn push(&mut self, value: T) {
+        self.values.push(value);
+    }
+
+    /// Removes the top value and returns it, or `None` if the stack is
+    /// empty. The capacity is left as it is.
+    pub fn pop(&mut self) -> Option<T> {
+        self.values.pop()
+    }
+
+    /// Shrinks the capacity to the number of values on the stack, freeing
+    /// the buffer if it is empty.
+    ///
+    /// On failure, aborts the process.
+    pub fn shrink_to_fit(&mut self) {
+        self.values.shrink_to_fit();
+    }
+}
+
+/// An empty stack, which does not allocate.
+impl<T> Default for RawStack<T> {
+    fn default() -> RawStack<T> {
+        RawStack::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use RawStack;
+    #[cfg(feature = "stats")]
+    use allocation_size;
+
+    #[test]
+    fn test_capacity_never_shrinks() {
+        let mut stack = RawStack::new();
+        let mut cap = stack.capacity();
+
+        for round in 0..4 {
+            for i in 0..100 * (round + 1) {
+                stack.push(i);
+                assert!(stack.capacity() >= cap);
+                cap = stack.capacity();
+            }
+
+            let ptr = stack.as_ptr();
+            while stack.pop().is_some() {
+                // Popping never reallocates.
+                assert_eq!(stack.capacity(), cap);
+                assert_eq!(stack.as_ptr(), ptr);
+            }
+        }
+        assert!(stack.is_empty());
+        assert_eq!(stack.capacity(), 512);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut stack = RawStack::<u64>::with_capacity(1000);
+        for i in 0..10 {
+            stack.push(i);
+        }
+
+        stack.shrink_to_fit();
+        assert_eq!(stack.capacity(), 10);
+        #[cfg(feature = "stats")]
+        assert_eq!(allocation_size(stack.as_ptr()), Some(80));
+        assert_eq!(stack.pop(), Some(9));
+
+        while stack.pop().is_some() {}
+        stack.shrink_to_fit();
+        assert_eq!(stack.capacity(), 0);
+    }
+
+    #[test]
+    fn test_drop_drops_values() {
+        let value = Rc::new(());
+        let mut stack = RawStack::new();
+        for _ in 0..7 {
+            stack.push(value.clone());
+        }
+        drop(stack.pop());
+        assert_eq!(Rc::strong_count(&value), 7);
+
+        drop(stack);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let mut stack = RawStack::new();
+        assert_eq!(stack.capacity(), usize::MAX);
+
+        for _ in 0..5 {
+            stack.push(());
+        }
+        stack.shrink_to_fit();
+        assert_eq!(stack.capacity(), usize::MAX);
+        assert_eq!(stack.pop(), Some(()));
+        assert_eq!(stack.len(), 4);
+    }
+}
@@ -0,0 +1,114 @@
+use std::mem;
+use std::ptr::{self, NonNull};
+
+use {alloc_zst_aware, dealloc_zst_aware};
+
+/// An owned allocation with room for `capacity` values of type `T`.
+///
+/// A `RawVec` does not know how many of its elements are initialized, so it
+/// never drops them; on drop it only frees the allocation. For the same
+/// reason it does not implement `Clone`, since there is no way to tell which
+/// elements could be cloned. Use `clone_from_slice` with the initialized
+/// prefix instead.
+pub struct RawVec<T> {
+    ptr: NonNull<T>,
+    cap: usize,
+}
+
+impl<T> RawVec<T> {
+    /// Allocates room for `cap` values of type `T`.
+    ///
+    /// A capacity of 0 does not allocate, and neither does a zero-sized `T`,
+    /// for which the capacity is `usize::MAX`. On failure, aborts the
+    /// process.
+    pub fn with_capacity(cap: usize) -> RawVec<T> {
+        let cap = if mem::size_of::<T>() == 0 { usize::MAX } else { cap };
+        let ptr = unsafe { NonNull::new_unchecked(alloc_zst_aware(cap)) };
+
+        RawVec { ptr, cap }
+    }
+
+    /// A pointer to the start of the allocation.
+    ///
+    /// Dangling, but well-aligned, when the capacity is 0.
+    #[inline]
+    pub fn ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// The number of values the allocation has room for.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+}
+
+impl<T: Clone> RawVec<T> {
+    /// Allocates room for exactly `src.len()` values and clones every element
+    /// of `src` into it.
+    ///
+    /// If a `clone` panics, the elements already cloned are leaked, as the
+    /// `RawVec` does not drop its elements.
+    pub fn clone_from_slice(src: &[T]) -> RawVec<T> {
+        let buf = RawVec::<T>::with_capacity(src.len());
+
+        for (i, elem) in src.iter().enumerate() {
+            unsafe { ptr::write(buf.ptr().add(i), elem.clone()) };
+        }
+
+        buf
+    }
+}
+
+/// An empty `RawVec`, which does not allocate.
+impl<T> Default for RawVec<T> {
+    fn default() -> RawVec<T> {
+        RawVec::with_capacity(0)
+    }
+}
+
+impl<T> Drop for RawVec<T> {
+    fn drop(&mut self) {
+        unsafe { dealloc_zst_aware(self.ptr.as_ptr(), self.cap) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use RawVec;
+
+    #[test]
+    fn test_default() {
+        let buf = RawVec::<u64>::default();
+        assert_eq!(buf.capacity(), 0);
+        assert!(!buf.ptr().is_null());
+        assert_eq!(buf.ptr() as usize % 8, 0);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let buf = RawVec::<()>::with_capacity(8);
+        assert_eq!(buf.capacity(), usize::MAX);
+
+        let cloned = RawVec::clone_from_slice(&[(); 3]);
+        assert_eq!(cloned.capacity(), usize::MAX);
+    }
+
+    #[test]
+    fn test_clone_from_slice() {
+        let src = vec![String::from("a"), String::from("bc"), String::from("def")];
+        let buf = RawVec::clone_from_slice(&src);
+        assert_eq!(buf.capacity(), 3);
+
+        unsafe {
+            for (i, elem) in src.iter().enumerate() {
+                let cloned = ptr::read(buf.ptr().add(i));
+                assert_eq!(&cloned, elem);
+            }
+        }
+
+        let empty = RawVec::<String>::clone_from_slice(&[]);
+        assert_eq!(empty.capacity(), 0);
+    }
+}
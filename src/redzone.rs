@@ -0,0 +1,124 @@
+//! Red zones around allocations, enabled by the `redzone` feature in debug
+//! builds.
+//!
+//! The typed functions over-allocate by `RED_ZONE` bytes, rounded up to
+//! whole elements, on either side of every allocation, fill that padding
+//! with `RED_ZONE_FILL`, and hand out a pointer past the leading zone. When
+//! the allocation is reallocated or freed both zones are checked, and a
+//! write which strayed out of bounds panics.
+//!
+//! In release builds, or without the feature, the zones are empty and every
+//! function here compiles down to nothing.
+
+use std::{mem, ptr, slice};
+
+/// Whether allocations are surrounded by red zones.
+pub(crate) const ENABLED: bool = cfg!(all(feature = "redzone", debug_assertions));
+
+/// The minimum number of bytes in each red zone.
+pub const RED_ZONE: usize = 16;
+
+/// The byte red zones are filled with.
+pub const RED_ZONE_FILL: u8 = 0xFE;
+
+/// The number of values of type `T` in each red zone.
+#[inline]
+fn zone<T>() -> usize {
+    if ENABLED && mem::size_of::<T>() != 0 {
+        RED_ZONE.div_ceil(mem::size_of::<T>())
+    } else {
+        0
+    }
+}
+
+/// The number of values of type `T` to allocate to hand out `size` of them.
+#[inline]
+pub(crate) fn padded<T>(size: usize) -> usize {
+    size.saturating_add(2 * zone::<T>())
+}
+
+/// Fills the red zones of the allocation of `size` values at `base`, and
+/// returns the pointer to hand out.
+#[inline]
+pub(crate) unsafe fn guard<T>(base: *mut T, size: usize) -> *mut T {
+    let zone = zone::<T>();
+    if zone != 0 {
+        let bytes = zone * mem::size_of::<T>();
+        ptr::write_bytes(base as *mut u8, RED_ZONE_FILL, bytes);
+        ptr::write_bytes(base.add(zone + size) as *mut u8, RED_ZONE_FILL, bytes);
+    }
+
+    base.add(zone)
+}
+
+/// The start of the underlying allocation behind `ptr`.
+#[inline]
+pub(crate) unsafe fn base<T>(ptr: *mut T) -> *mut T {
+    ptr.sub(zone::<T>())
+}
+
+/// Panics if either red zone of the allocation of `size` values at `ptr`
+/// has been written to.
+#[inline]
+pub(crate) unsafe fn check<T>(ptr: *mut T, size: usize) {
+    let zone = zone::<T>();
+    if zone == 0 {
+        return;
+    }
+
+    let bytes = zone * mem::size_of::<T>();
+    let before = slice::from_raw_parts(base(ptr) as *const u8, bytes);
+    let after = slice::from_raw_parts(ptr.add(size) as *const u8, bytes);
+
+    assert!(before.iter().all(|&b| b == RED_ZONE_FILL),
+            "memalloc: write before the start of the allocation of {} elements at {:p}",
+            size, ptr);
+    assert!(after.iter().all(|&b| b == RED_ZONE_FILL),
+            "memalloc: write past the end of the allocation of {} elements at {:p}",
+            size, ptr);
+}
+
+#[cfg(all(test, feature = "redzone", debug_assertions))]
+mod tests {
+    use std::ptr;
+    use {typed_alloc, typed_dealloc, typed_realloc};
+
+    #[test]
+    fn test_in_bounds() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(10);
+            for i in 0..10 {
+                ptr::write(buffer.add(i), i as u32);
+            }
+
+            let buffer = typed_realloc(buffer, 10, 100);
+            ptr::write(buffer.add(99), 99);
+            let buffer = typed_realloc(buffer, 100, 5);
+            assert_eq!(ptr::read(buffer.add(4)), 4);
+
+            typed_dealloc(buffer, 5);
+        }
+    }
+
+    // An out-of-bounds write lands in the red zone, which is then noticed
+    // when the allocation is freed.
+    #[test]
+    #[should_panic(expected = "write past the end")]
+    fn test_overrun_detected() {
+        unsafe {
+            let buffer = typed_alloc::<u8>(10);
+            ptr::write(buffer.add(10), 0);
+            typed_dealloc(buffer, 10);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "write before the start")]
+    fn test_underrun_detected() {
+        unsafe {
+            let buffer = typed_alloc::<u64>(2);
+            ptr::write(buffer.sub(1), 0);
+            typed_realloc(buffer, 2, 4);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Bookkeeping of live allocations, enabled by the `stats` feature.
+//!
+//! Every allocation made through the typed functions is recorded by
+//! address, along with its size in bytes and, for allocations made through
+//! `typed_alloc_located`, the location of the caller. With the `backtrace`
+//! feature, the full backtrace of every allocation is captured as well.
+//!
+//! The registry is shared by the whole process, so an allocation made on one
+//! thread can be reallocated or freed on another and is still accounted for
+//! correctly. It is split into shards by address, each behind its own lock,
+//! to keep threads allocating at the same time from contending on a single
+//! lock.
+//!
+//! Without the `stats` feature every hook here is an empty inline function.
+
+use std::panic::Location;
+
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
+
+#[cfg(feature = "stats")]
+use std::collections::BTreeMap;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "stats")]
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "stats")]
+struct Record {
+    bytes: usize,
+    location: Option<&'static Location<'static>>,
+    #[cfg(feature = "backtrace")]
+    backtrace: Backtrace,
+}
+
+#[cfg(feature = "stats")]
+impl Record {
+    /// A record of a fresh allocation of `bytes` bytes.
+    fn new(bytes: usize) -> Record {
+        Record {
+            bytes,
+            location: None,
+            #[cfg(feature = "backtrace")]
+            backtrace: Backtrace::force_capture(),
+        }
+    }
+}
+
+/// Running totals of the operations made in the process, kept for the
+/// `metrics` feature.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Counters {
+    /// Fresh allocations made.
+    pub allocations: usize,
+    /// Reallocations which moved the allocation to a new address.
+    pub reallocs_moved: usize,
+    /// Reallocations which kept the allocation at the same address.
+    pub reallocs_in_place: usize,
+}
+
+/// The number of shards the registry is split into.
+#[cfg(feature = "stats")]
+const SHARDS: usize = 16;
+
+#[cfg(feature = "stats")]
+static LIVE: [Mutex<BTreeMap<usize, Record>>; SHARDS] = [const { Mutex::new(BTreeMap::new()) }; SHARDS];
+
+#[cfg(feature = "stats")]
+static MOVED: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+#[cfg(feature = "metrics")]
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "metrics")]
+static REALLOCS_MOVED: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "metrics")]
+static REALLOCS_IN_PLACE: AtomicUsize = AtomicUsize::new(0);
+
+/// Locks `mutex`, carrying on if a thread panicked while holding it: every
+/// update leaves the maps consistent.
+#[cfg(feature = "stats")]
+fn lock<T>(mutex: &'static Mutex<T>) -> MutexGuard<'static, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Locks the shard of the registry holding the allocation at `addr`.
+#[cfg(feature = "stats")]
+fn shard(addr: usize) -> MutexGuard<'static, BTreeMap<usize, Record>> {
+    // Allocations are at least 16-byte aligned with most allocators, so the
+    // low bits carry no information.
+    lock(&LIVE[(addr >> 4) % SHARDS])
+}
+
+/// Runs `f` on every shard of the registry in turn.
+#[cfg(feature = "stats")]
+fn each_shard<F: FnMut(&BTreeMap<usize, Record>)>(mut f: F) {
+    for live in &LIVE {
+        f(&lock(live));
+    }
+}
+
+/// Runs `f` on the map from the old to the new addresses of relocated
+/// allocations. The map is only kept in debug builds.
+#[cfg(feature = "stats")]
+fn with_moved<R: Default, F: FnOnce(&mut BTreeMap<usize, usize>) -> R>(f: F) -> R {
+    if cfg!(debug_assertions) {
+        f(&mut lock(&MOVED))
+    } else {
+        R::default()
+    }
+}
+
+/// Returns the running totals of the operations made in the process.
+#[cfg(feature = "metrics")]
+pub(crate) fn counters() -> Counters {
+    Counters {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        reallocs_moved: REALLOCS_MOVED.load(Ordering::Relaxed),
+        reallocs_in_place: REALLOCS_IN_PLACE.load(Ordering::Relaxed),
+    }
+}
+
+/// Records a fresh allocation of `bytes` bytes at `ptr`.
+#[inline]
+pub(crate) fn on_alloc<T>(ptr: *mut T, bytes: usize) {
+    #[cfg(feature = "stats")]
+    {
+        if bytes != 0 {
+            // Captured before locking, since a backtrace can be slow.
+            let record = Record::new(bytes);
+            let mut live = shard(ptr as usize);
+            live.insert(ptr as usize, record);
+            // Under the shard's lock, so that a relocation away from this
+            // address recorded by `Realloc::finish` can't land afterwards.
+            with_moved(|moved| moved.remove(&(ptr as usize)));
+            drop(live);
+            #[cfg(feature = "metrics")]
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    #[cfg(not(feature = "stats"))]
+    let _ = (ptr, bytes);
+}
+
+/// The record of an allocation being reallocated, taken out of the registry
+/// by `begin_realloc`.
+///
+/// Once the old memory is freed another thread can be handed the same
+/// address and record its own allocation there, so the old record must be
+/// out of the registry before the allocator is called. `finish` records the
+/// allocation at its new address. If the reallocation fails, or panics,
+/// dropping the `Realloc` puts the record back at the old address.
+pub(crate) struct Realloc {
+    #[cfg(feature = "stats")]
+    old: usize,
+    #[cfg(feature = "stats")]
+    record: Option<Record>,
+}
+
+/// Takes the record of the allocation at `old_ptr` out of the registry,
+/// before it is reallocated.
+#[inline]
+pub(crate) fn begin_realloc<T>(old_ptr: *mut T) -> Realloc {
+    #[cfg(feature = "stats")]
+    {
+        let old = old_ptr as usize;
+        Realloc { old, record: shard(old).remove(&old) }
+    }
+    #[cfg(not(feature = "stats"))]
+    {
+        let _ = old_ptr;
+        Realloc {}
+    }
+}
+
+impl Realloc {
+    /// Records that the allocation now lives at `new_ptr` and is `bytes`
+    /// bytes long.
+    #[inline]
+    pub(crate) fn finish<T>(mut self, new_ptr: *mut T, bytes: usize) {
+        #[cfg(feature = "stats")]
+        {
+            let (old, new) = (self.old, new_ptr as usize);
+
+            // By now the old address may have been handed out again, in
+            // which case it is no longer stale.
+            if old != new && bytes != 0 {
+                let live = shard(old);
+                if !live.contains_key(&old) {
+                    with_moved(|moved| moved.insert(old, new));
+                }
+            }
+            with_moved(|moved| moved.remove(&new));
+
+            // The record keeps the location and backtrace of the original
+            // allocation as it moves. Memory the registry never saw, such as
+            // a global allocation passed to `realloc_layout`, stays unseen.
+            if let Some(record) = self.record.take() {
+                if bytes != 0 {
+                    shard(new).insert(new, Record { bytes, ..record });
+                }
+            }
+
+            #[cfg(feature = "metrics")]
+            if old == new {
+                REALLOCS_IN_PLACE.fetch_add(1, Ordering::Relaxed);
+            } else {
+                REALLOCS_MOVED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        #[cfg(not(feature = "stats"))]
+        let _ = (&mut self, new_ptr, bytes);
+    }
+}
+
+#[cfg(feature = "stats")]
+impl Drop for Realloc {
+    fn drop(&mut self) {
+        // Only left if `finish` was never reached, so the allocation is
+        // still at its old address.
+        if let Some(record) = self.record.take() {
+            shard(self.old).insert(self.old, record);
+        }
+    }
+}
+
+/// Records that the allocation at `ptr`, believed by the caller to be
+/// `bytes` bytes long, was freed.
+///
+/// In debug builds, panics if the allocation was recorded with a different
+/// size, before anything is freed.
+#[inline]
+pub(crate) fn on_dealloc<T>(ptr: *mut T, bytes: usize) {
+    #[cfg(feature = "stats")]
+    {
+        let mut live = shard(ptr as usize);
+        if cfg!(debug_assertions) {
+            if let Some(recorded) = live.get(&(ptr as usize)).map(|record| record.bytes) {
+                // Unlock before panicking, so other threads can carry on.
+                if recorded != bytes {
+                    drop(live);
+                    panic!("memalloc: freeing {} bytes at {:p}, but {} bytes were allocated there",
+                           bytes, ptr, recorded);
+                }
+            }
+        }
+
+        live.remove(&(ptr as usize));
+    }
+    #[cfg(not(feature = "stats"))]
+    let _ = (ptr, bytes);
+}
+
+/// Attributes the allocation at `ptr` to `location`.
+#[inline]
+pub(crate) fn set_location<T>(ptr: *mut T, location: &'static Location<'static>) {
+    #[cfg(feature = "stats")]
+    {
+        if let Some(record) = shard(ptr as usize).get_mut(&(ptr as usize)) {
+            record.location = Some(location);
+        }
+    }
+    #[cfg(not(feature = "stats"))]
+    let _ = (ptr, location);
+}
+
+/// Returns the size in bytes recorded for the live allocation at `ptr`, or
+/// `None` if no allocation lives there.
+///
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+pub fn allocation_size<T>(ptr: *const T) -> Option<usize> {
+    shard(ptr as usize).get(&(ptr as usize)).map(|record| record.bytes)
+}
+
+/// Returns whether `ptr` is the start of a live allocation made through
+/// this crate.
+///
+/// This lets code mixing allocators check that a pointer came from memalloc
+/// before freeing it through memalloc. The answer comes from the registry, so
+/// it is only available with the `stats` feature, which costs a map entry per
+/// allocation and a locked lookup on every allocation, reallocation and free.
+/// Pointers to the inside of an allocation are not owned.
+#[cfg(feature = "stats")]
+pub fn owns(ptr: *const u8) -> bool {
+    shard(ptr as usize).contains_key(&(ptr as usize))
+}
+
+/// Returns the address an allocation was moved to, if `stale` is where it
+/// lived before being relocated.
+///
+/// Meant for debugging a read through a stale pointer, for instance one that
+/// found `MOVED_FILL`. An address stops being reported once it is handed out
+/// again. Relocations are only recorded in debug builds, so in release builds
+/// this always returns `None`.
+///
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+pub fn relocated_to(stale: *const u8) -> Option<usize> {
+    with_moved(|moved| moved.get(&(stale as usize)).cloned())
+}
+
+/// Returns the total size in bytes of the live allocations.
+///
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+pub fn live_bytes() -> usize {
+    let mut bytes = 0;
+    each_shard(|live| bytes += live.values().map(|record| record.bytes).sum::<usize>());
+
+    bytes
+}
+
+/// Returns `(size_bytes, count)` pairs counting the live allocations by
+/// their exact size in bytes, ordered by size.
+///
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+pub fn size_histogram() -> Vec<(usize, usize)> {
+    let mut counts = BTreeMap::new();
+    each_shard(|live| {
+        for record in live.values() {
+            *counts.entry(record.bytes).or_insert(0) += 1;
+        }
+    });
+
+    counts.into_iter().collect()
+}
+
+/// Returns the address, size in bytes and caller location of every live
+/// allocation made through `typed_alloc_located`, ordered by address.
+///
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+pub fn live_allocations() -> Vec<(usize, usize, &'static Location<'static>)> {
+    let mut located = Vec::new();
+
+    each_shard(|live| {
+        located.extend(live.iter().filter_map(|(&addr, record)| {
+            record.location.map(|location| (addr, record.bytes, location))
+        }));
+    });
+    located.sort_by_key(|&(addr, _, _)| addr);
+
+    located
+}
+
+/// Returns a report of every live allocation, ordered by address, each with
+/// its address, size in bytes and the backtrace of the call that made it.
+///
+/// Capturing a backtrace on every allocation is slow and uses a lot of
+/// memory, so this is only available with the `backtrace` feature, which is
+/// off by default and meant for hunting down a leak. Symbols are resolved
+/// when the report is formatted, so building with debug info gives the most
+/// useful output.
+#[cfg(feature = "backtrace")]
+pub fn dump_leaks() -> String {
+    use std::fmt::Write;
+
+    // Each entry is formatted while its shard is locked, then the entries
+    // are put in order.
+    let mut entries = Vec::new();
+    each_shard(|live| {
+        for (&addr, record) in live.iter() {
+            let mut entry = String::new();
+            let _ = writeln!(entry, "{} bytes at {:#x}, allocated at:\n{}",
+                             record.bytes, addr, record.backtrace);
+            entries.push((addr, entry));
+        }
+    });
+    entries.sort_by_key(|&(addr, _)| addr);
+
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
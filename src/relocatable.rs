@@ -0,0 +1,161 @@
+use std::mem;
+use std::ptr::NonNull;
+
+use {alloc_zst_aware, dealloc_zst_aware, typed_alloc, typed_dealloc, typed_realloc};
+
+/// An allocation of `size` values of type `T` which tells registered
+/// observers when resizing moves it.
+///
+/// Structures holding pointers into the buffer, such as intrusive lists,
+/// can register a callback with `on_move`. Whenever `resize` relocates the
+/// buffer, every callback is called with the old and new base pointers, so
+/// the observers can rebase their pointers. Like the raw functions,
+/// `Relocatable` never drops the values in the allocation.
+pub struct Relocatable<T> {
+    ptr: *mut T,
+    size: usize,
+    on_move: Vec<Box<dyn FnMut(*mut T, *mut T)>>,
+}
+
+impl<T> Relocatable<T> {
+    /// Allocates room for `size` values of type `T`, which are left
+    /// uninitialized.
+    ///
+    /// A size of 0 does not allocate, and neither does a zero-sized `T`,
+    /// which is never moved. On failure, aborts the process.
+    pub fn new(size: usize) -> Relocatable<T> {
+        Relocatable { ptr: unsafe { alloc_zst_aware(size) }, size, on_move: Vec::new() }
+    }
+
+    /// A pointer to the start of the allocation, valid until it is moved
+    /// by `resize`.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr
+    }
+
+    /// The number of values the allocation has room for.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the allocation has room for no values.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Registers `callback` to be called with the old and new base pointers
+    /// every time `resize` moves the allocation.
+    pub fn on_move<F: FnMut(*mut T, *mut T) + 'static>(&mut self, callback: F) {
+        self.on_move.push(Box::new(callback));
+    }
+
+    /// Resizes the allocation to hold `new_size` values, preserving the
+    /// first `min(len, new_size)` of them.
+    ///
+    /// If the allocation moved, the callbacks registered with `on_move` are
+    /// called in the order they were registered, after the values have been
+    /// moved. The memory at the old pointer is already freed by then, so the
+    /// callbacks may only compare or rebase the old pointer, not read
+    /// through it.
+    ///
+    /// On failure, aborts the process.
+    pub fn resize(&mut self, new_size: usize) {
+        if mem::size_of::<T>() == 0 {
+            self.size = new_size;
+            return;
+        }
+
+        let old_ptr = self.ptr;
+
+        self.ptr = unsafe {
+            match (self.size, new_size) {
+                (old, new) if old == new => old_ptr,
+                (0, new) => typed_alloc(new),
+                (old, 0) => {
+                    typed_dealloc(old_ptr, old);
+                    NonNull::dangling().as_ptr()
+                },
+                (old, new) => typed_realloc(old_ptr, old, new),
+            }
+        };
+        self.size = new_size;
+
+        if self.ptr != old_ptr {
+            for callback in &mut self.on_move {
+                callback(old_ptr, self.ptr);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Relocatable<T> {
+    fn drop(&mut self) {
+        unsafe { dealloc_zst_aware(self.ptr, self.size) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::ptr;
+    use std::rc::Rc;
+    use Relocatable;
+
+    #[test]
+    fn test_callbacks_fire_on_relocation() {
+        let moves = Rc::new(RefCell::new(Vec::new()));
+        let mut buffer = Relocatable::<u64>::new(4);
+        unsafe { ptr::write(buffer.as_ptr().add(3), 42) };
+
+        let recorded = moves.clone();
+        buffer.on_move(move |old, new| recorded.borrow_mut().push((old as usize, new as usize)));
+
+        let mut expected = Vec::new();
+        for size in (4..32).chain((4..31).rev()) {
+            let old = buffer.as_ptr();
+            buffer.resize(size);
+
+            if buffer.as_ptr() != old {
+                expected.push((old as usize, buffer.as_ptr() as usize));
+            }
+            assert_eq!(unsafe { ptr::read(buffer.as_ptr().add(3)) }, 42);
+        }
+
+        assert_eq!(*moves.borrow(), expected);
+        assert_eq!(buffer.len(), 4);
+    }
+
+    #[test]
+    fn test_no_move_no_callback() {
+        let fired = Rc::new(RefCell::new(0));
+        let mut buffer = Relocatable::<u8>::new(16);
+
+        let counter = fired.clone();
+        buffer.on_move(move |_, _| *counter.borrow_mut() += 1);
+
+        buffer.resize(16);
+        assert_eq!(*fired.borrow(), 0);
+
+        buffer.resize(0);
+        assert!(buffer.is_empty());
+        assert_eq!(*fired.borrow(), 1);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let fired = Rc::new(RefCell::new(0));
+        let mut buffer = Relocatable::<()>::new(4);
+
+        let counter = fired.clone();
+        buffer.on_move(move |_, _| *counter.borrow_mut() += 1);
+
+        for &size in &[100, 0, 8] {
+            buffer.resize(size);
+            assert_eq!(buffer.len(), size);
+        }
+        assert_eq!(*fired.borrow(), 0);
+    }
+}
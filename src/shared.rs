@@ -0,0 +1,303 @@
+use std::mem;
+use std::ptr::NonNull;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+use {alloc_zst_aware, dealloc_zst_aware};
+
+/// A reference-counted allocation of `size` values of type `T`, freed when
+/// the last handle is dropped.
+///
+/// The count is atomic, so handles can be sent and shared between threads
+/// when `T` is `Send` and `Sync`. Synchronizing access to the memory itself
+/// is up to the caller. Like the raw functions, `SharedAlloc` never drops
+/// the values in the allocation.
+///
+/// `downgrade` makes a `WeakAlloc`, which doesn't keep the allocation alive,
+/// for breaking cycles between buffers.
+pub struct SharedAlloc<T> {
+    ptr: NonNull<T>,
+    size: usize,
+    counts: NonNull<Counts>,
+}
+
+/// A non-owning handle to a `SharedAlloc`'s allocation, which can be
+/// upgraded back to a `SharedAlloc` while any strong handles remain.
+///
+/// Like `std::rc::Weak`, a `WeakAlloc` keeps the counts alive but not the
+/// memory: the allocation is freed when the last `SharedAlloc` is dropped,
+/// and the counts once the last `WeakAlloc` is too.
+pub struct WeakAlloc<T> {
+    ptr: NonNull<T>,
+    size: usize,
+    counts: NonNull<Counts>,
+}
+
+/// The counts shared by every handle to an allocation. The strong handles
+/// together hold one weak reference, released when the last of them drops.
+struct Counts {
+    strong: AtomicUsize,
+    weak: AtomicUsize,
+}
+
+unsafe impl<T: Send + Sync> Send for SharedAlloc<T> {}
+unsafe impl<T: Send + Sync> Sync for SharedAlloc<T> {}
+unsafe impl<T: Send + Sync> Send for WeakAlloc<T> {}
+unsafe impl<T: Send + Sync> Sync for WeakAlloc<T> {}
+
+impl<T> SharedAlloc<T> {
+    /// Allocates room for `size` values of type `T`, which are left
+    /// uninitialized.
+    ///
+    /// A size of 0 does not allocate, and neither does a zero-sized `T`. On
+    /// failure, aborts the process.
+    pub fn new(size: usize) -> SharedAlloc<T> {
+        let ptr = unsafe { NonNull::new_unchecked(alloc_zst_aware(size)) };
+        let counts = Box::new(Counts { strong: AtomicUsize::new(1), weak: AtomicUsize::new(1) });
+
+        SharedAlloc {
+            ptr,
+            size,
+            counts: unsafe { NonNull::new_unchecked(Box::into_raw(counts)) },
+        }
+    }
+
+    /// A pointer to the start of the allocation.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// The number of values the allocation has room for.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the allocation has room for no values.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// The number of handles sharing the allocation.
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        self.counts().strong.load(Ordering::Acquire)
+    }
+
+    /// The number of `WeakAlloc`s referring to the allocation.
+    #[inline]
+    pub fn weak_count(&self) -> usize {
+        self.counts().weak.load(Ordering::Acquire) - 1
+    }
+
+    /// Makes a `WeakAlloc` referring to the same allocation, which doesn't
+    /// keep it alive.
+    pub fn downgrade(&self) -> WeakAlloc<T> {
+        self.counts().weak.fetch_add(1, Ordering::Relaxed);
+
+        WeakAlloc { ptr: self.ptr, size: self.size, counts: self.counts }
+    }
+
+    /// Returns the pointer and size of the allocation if this is the only
+    /// handle, making the caller responsible for freeing it with
+    /// `dealloc_zst_aware`, or `typed_dealloc` if `T` is not zero-sized.
+    /// Otherwise, returns the handle unchanged.
+    pub fn try_unwrap(self) -> Result<(*mut T, usize), SharedAlloc<T>> {
+        let strong = &self.counts().strong;
+        if strong.compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return Err(self);
+        }
+
+        let parts = (self.ptr.as_ptr(), self.size);
+        unsafe { release_weak(self.counts) };
+        mem::forget(self);
+
+        Ok(parts)
+    }
+
+    #[inline]
+    fn counts(&self) -> &Counts {
+        unsafe { self.counts.as_ref() }
+    }
+}
+
+impl<T> WeakAlloc<T> {
+    /// Returns a new `SharedAlloc` for the allocation, or `None` if every
+    /// `SharedAlloc` has been dropped and the memory freed.
+    pub fn upgrade(&self) -> Option<SharedAlloc<T>> {
+        let strong = &self.counts().strong;
+        let mut count = strong.load(Ordering::Relaxed);
+
+        loop {
+            if count == 0 {
+                return None;
+            }
+
+            match strong.compare_exchange_weak(count, count + 1, Ordering::Acquire,
+                                               Ordering::Relaxed) {
+                Ok(_) => return Some(SharedAlloc { ptr: self.ptr, size: self.size,
+                                                   counts: self.counts }),
+                Err(current) => count = current,
+            }
+        }
+    }
+
+    /// The number of `SharedAlloc`s keeping the allocation alive, 0 once it
+    /// has been freed.
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        self.counts().strong.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn counts(&self) -> &Counts {
+        unsafe { self.counts.as_ref() }
+    }
+}
+
+/// Releases a weak reference to `counts`, freeing them if it was the last.
+unsafe fn release_weak(counts: NonNull<Counts>) {
+    if counts.as_ref().weak.fetch_sub(1, Ordering::Release) == 1 {
+        atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(counts.as_ptr()));
+    }
+}
+
+/// Returns another handle to the same allocation.
+impl<T> Clone for SharedAlloc<T> {
+    fn clone(&self) -> SharedAlloc<T> {
+        self.counts().strong.fetch_add(1, Ordering::Relaxed);
+
+        SharedAlloc { ptr: self.ptr, size: self.size, counts: self.counts }
+    }
+}
+
+/// Returns another weak handle to the same allocation.
+impl<T> Clone for WeakAlloc<T> {
+    fn clone(&self) -> WeakAlloc<T> {
+        self.counts().weak.fetch_add(1, Ordering::Relaxed);
+
+        WeakAlloc { ptr: self.ptr, size: self.size, counts: self.counts }
+    }
+}
+
+impl<T> Drop for SharedAlloc<T> {
+    fn drop(&mut self) {
+        if self.counts().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        // Make every other handle's use of the memory happen before it is
+        // freed.
+        atomic::fence(Ordering::Acquire);
+
+        unsafe {
+            dealloc_zst_aware(self.ptr.as_ptr(), self.size);
+            release_weak(self.counts);
+        }
+    }
+}
+
+impl<T> Drop for WeakAlloc<T> {
+    fn drop(&mut self) {
+        unsafe { release_weak(self.counts) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::thread;
+    use {dealloc_zst_aware, typed_dealloc, SharedAlloc};
+    #[cfg(feature = "stats")]
+    use allocation_size;
+
+    #[test]
+    fn test_clone_and_drop() {
+        let shared = SharedAlloc::<u32>::new(4);
+        unsafe { ptr::write(shared.as_ptr(), 5) };
+
+        let clones = (0..5).map(|_| shared.clone()).collect::<Vec<_>>();
+        assert_eq!(shared.strong_count(), 6);
+        assert_eq!(unsafe { ptr::read(clones[4].as_ptr()) }, 5);
+
+        drop(clones);
+        assert_eq!(shared.strong_count(), 1);
+
+        // With every clone gone, this is the last handle, so the memory
+        // is handed back exactly once.
+        let (ptr, size) = shared.try_unwrap().ok().unwrap();
+        assert_eq!(size, 4);
+        unsafe { typed_dealloc(ptr, size) };
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let shared = SharedAlloc::<()>::new(3);
+        let other = shared.clone();
+        unsafe { ptr::write(other.as_ptr(), ()) };
+        drop(other);
+
+        let (ptr, size) = shared.try_unwrap().ok().unwrap();
+        unsafe { dealloc_zst_aware(ptr, size) };
+    }
+
+    #[test]
+    fn test_try_unwrap_shared() {
+        let shared = SharedAlloc::<u8>::new(16);
+        let other = shared.clone();
+
+        let shared = shared.try_unwrap().err().unwrap();
+        assert_eq!(shared.strong_count(), 2);
+        drop(other);
+        assert_eq!(shared.strong_count(), 1);
+    }
+
+    #[test]
+    fn test_across_threads() {
+        let shared = SharedAlloc::<u64>::new(8);
+
+        let handles = (0..8).map(|i| {
+            let shared = shared.clone();
+            thread::spawn(move || unsafe { ptr::write(shared.as_ptr().add(i), i as u64) })
+        }).collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(shared.strong_count(), 1);
+        assert_eq!(unsafe { ptr::read(shared.as_ptr().add(7)) }, 7);
+    }
+
+    #[test]
+    fn test_weak_upgrade() {
+        let shared = SharedAlloc::<u32>::new(4);
+        unsafe { ptr::write(shared.as_ptr(), 9) };
+        let ptr = shared.as_ptr();
+
+        let weak = shared.downgrade();
+        let other_weak = weak.clone();
+        assert_eq!(shared.weak_count(), 2);
+        assert_eq!(shared.strong_count(), 1);
+
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(unsafe { ptr::read(upgraded.as_ptr()) }, 9);
+        assert_eq!(weak.strong_count(), 2);
+
+        drop(shared);
+        drop(upgraded);
+
+        // With every strong handle gone the memory is freed, while the weak
+        // handles keep only the counts alive.
+        assert!(weak.upgrade().is_none());
+        assert_eq!(other_weak.strong_count(), 0);
+        #[cfg(feature = "stats")]
+        assert_eq!(allocation_size(ptr), None);
+        let _ = ptr;
+
+        drop(weak);
+        drop(other_weak);
+    }
+}
@@ -0,0 +1,63 @@
+use std::any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// The registered size classes, keyed by type name.
+static CLASSES: RwLock<Vec<(&'static str, Vec<usize>)>> = RwLock::new(Vec::new());
+
+/// Set once any classes are registered, so unregistered programs don't pay
+/// for the lookup.
+static ANY_CLASSES: AtomicBool = AtomicBool::new(false);
+
+/// Installs size classes for `T`: requests for `n` values of type `T` are
+/// rounded up to the smallest class of at least `n`. Requests larger than
+/// every class are not rounded.
+///
+/// This lets workloads dominated by a few element types trade some memory
+/// for less fragmentation. Types without classes are never rounded.
+/// Registering classes again for the same type replaces them.
+///
+/// Classes are keyed by `std::any::type_name`, since the allocation
+/// functions place no `'static` bound on `T` that would allow keying on
+/// `TypeId`.
+///
+/// # Safety
+///
+/// Rounding is applied by every typed allocation, reallocation and
+/// deallocation function, so an allocation must be freed under the same
+/// classes it was made under. Behavior is undefined if any allocation of
+/// `T` is live when this is called, including when replacing classes
+/// registered earlier.
+pub unsafe fn register_size_classes<T>(classes: &[usize]) {
+    let mut sorted = classes.to_vec();
+    sorted.sort_unstable();
+
+    let name = any::type_name::<T>();
+    let mut registered = CLASSES.write().unwrap_or_else(|e| e.into_inner());
+
+    match registered.iter_mut().find(|entry| entry.0 == name) {
+        Some(entry) => entry.1 = sorted,
+        None => registered.push((name, sorted)),
+    }
+
+    ANY_CLASSES.store(true, Ordering::Release);
+}
+
+/// Returns the number of values a request for `size` values of type `T`
+/// is rounded up to by the classes installed with `register_size_classes`.
+///
+/// This is the capacity `typed_alloc::<T>(size)` actually allocates.
+#[inline]
+pub fn size_class<T>(size: usize) -> usize {
+    if !ANY_CLASSES.load(Ordering::Acquire) {
+        return size;
+    }
+
+    let name = any::type_name::<T>();
+    let registered = CLASSES.read().unwrap_or_else(|e| e.into_inner());
+
+    registered.iter()
+        .find(|entry| entry.0 == name)
+        .and_then(|entry| entry.1.iter().find(|&&class| class >= size))
+        .map_or(size, |&class| class)
+}
@@ -0,0 +1,132 @@
+use std::ptr;
+use std::slice;
+
+use {alloc_zst_aware, dealloc_zst_aware};
+
+/// An owned, fixed-length allocation of initialized `T`s, borrowed as a
+/// slice.
+///
+/// Unlike `alloc_uninit_slice`, whose lifetime is chosen by the caller,
+/// the slices returned by `as_slice` and `as_mut_slice` borrow the
+/// `SliceAlloc`, so they can't outlive the memory. On drop, the elements
+/// are dropped and the allocation is freed.
+///
+/// ```compile_fail
+/// use memalloc::SliceAlloc;
+///
+/// let slice;
+/// {
+///     let alloc = SliceAlloc::<u8>::new(4);
+///     slice = alloc.as_slice();
+/// }
+/// println!("{:?}", slice);
+/// ```
+pub struct SliceAlloc<T> {
+    ptr: *mut T,
+    len: usize,
+}
+
+impl<T: Default> SliceAlloc<T> {
+    /// Allocates `len` values of type `T`, each initialized to
+    /// `T::default()`.
+    ///
+    /// A length of 0 does not allocate. On failure, aborts the process.
+    pub fn new(len: usize) -> SliceAlloc<T> {
+        // If `default` panics the allocation and the values written so far
+        // are leaked, but never dropped uninitialized.
+        let ptr = unsafe { alloc_zst_aware::<T>(len) };
+        for i in 0..len {
+            unsafe { ptr::write(ptr.add(i), T::default()) };
+        }
+
+        SliceAlloc { ptr, len }
+    }
+}
+
+impl<T: Clone> SliceAlloc<T> {
+    /// Allocates `src.len()` values of type `T` and clones `src` into them.
+    ///
+    /// On failure, aborts the process.
+    ///
+    /// If a `clone` panics the allocation and the values cloned so far are
+    /// leaked.
+    pub fn from_slice(src: &[T]) -> SliceAlloc<T> {
+        let ptr = unsafe { alloc_zst_aware::<T>(src.len()) };
+        for (i, elem) in src.iter().enumerate() {
+            unsafe { ptr::write(ptr.add(i), elem.clone()) };
+        }
+
+        SliceAlloc { ptr, len: src.len() }
+    }
+}
+
+impl<T> SliceAlloc<T> {
+    /// The number of elements.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Borrows the elements as a slice.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Borrows the elements as a mutable slice.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<T> Drop for SliceAlloc<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.as_mut_slice() as *mut [T]);
+
+            dealloc_zst_aware(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use SliceAlloc;
+
+    #[test]
+    fn test_borrow_slice() {
+        let mut alloc = SliceAlloc::<u32>::new(5);
+        assert_eq!(alloc.as_slice(), &[0; 5]);
+
+        for (i, x) in alloc.as_mut_slice().iter_mut().enumerate() {
+            *x = i as u32 * 3;
+        }
+        assert_eq!(alloc.as_slice(), &[0, 3, 6, 9, 12]);
+        assert_eq!(alloc.len(), 5);
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let alloc = SliceAlloc::from_slice(&["a".to_string(), "b".to_string()]);
+        assert_eq!(alloc.as_slice(), &["a", "b"]);
+
+        let empty = SliceAlloc::<String>::from_slice(&[]);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let alloc = SliceAlloc::<()>::new(7);
+        assert_eq!(alloc.as_slice(), &[(); 7]);
+
+        let cloned = SliceAlloc::from_slice(alloc.as_slice());
+        assert_eq!(cloned.len(), 7);
+    }
+}
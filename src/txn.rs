@@ -0,0 +1,124 @@
+use std::cmp;
+use std::mem;
+use std::ptr;
+
+use {typed_alloc, typed_dealloc};
+
+/// A pending resize of an allocation, returned by `begin_grow`.
+///
+/// Both the old and the new allocation stay valid until the resize is
+/// either committed, adopting the new allocation, or rolled back, keeping
+/// the old one. Dropping a `ReserveTxn` rolls it back.
+pub struct ReserveTxn<T> {
+    old_ptr: *mut T,
+    old_size: usize,
+    new_ptr: *mut T,
+    new_size: usize,
+}
+
+/// Begins resizing the allocation referenced by `ptr` to `new_size` values
+/// of type `T`.
+///
+/// A new allocation is made and the first `min(old_size, new_size)` values
+/// are copied into it, leaving the original untouched. The new allocation
+/// can be worked on through `ReserveTxn::new_ptr` before deciding whether to
+/// keep it.
+///
+/// On failure, aborts the process.
+///
+/// # Safety
+///
+/// Behavior is undefined if `new_size` is 0.
+///
+/// The `old_size` parameter is the size used to create the allocation
+/// referenced by `ptr`, or the `new_size` passed to previous reallocations.
+/// The caller must not free `ptr` while the transaction is pending.
+pub unsafe fn begin_grow<T>(ptr: *mut T, old_size: usize, new_size: usize) -> ReserveTxn<T> {
+    let new_ptr = typed_alloc(new_size);
+    ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(old_size, new_size));
+
+    ReserveTxn { old_ptr: ptr, old_size, new_ptr, new_size }
+}
+
+impl<T> ReserveTxn<T> {
+    /// A pointer to the new allocation.
+    #[inline]
+    pub fn new_ptr(&self) -> *mut T {
+        self.new_ptr
+    }
+
+    /// Frees the old allocation and returns the new one, which holds
+    /// `new_size` values.
+    pub fn commit(self) -> *mut T {
+        let new_ptr = self.new_ptr;
+        unsafe { typed_dealloc(self.old_ptr, self.old_size) };
+        mem::forget(self);
+
+        new_ptr
+    }
+
+    /// Frees the new allocation and returns the old one, which holds
+    /// `old_size` values as before.
+    pub fn rollback(self) -> *mut T {
+        let old_ptr = self.old_ptr;
+        drop(self);
+
+        old_ptr
+    }
+}
+
+impl<T> Drop for ReserveTxn<T> {
+    fn drop(&mut self) {
+        unsafe { typed_dealloc(self.new_ptr, self.new_size) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use {begin_grow, typed_alloc, typed_dealloc};
+
+    #[test]
+    fn test_rollback() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(4);
+            for i in 0..4 {
+                ptr::write(buffer.offset(i), i as u32);
+            }
+
+            let txn = begin_grow(buffer, 4, 8);
+            assert!(txn.new_ptr() != buffer);
+            ptr::write(txn.new_ptr(), 100);
+
+            let rolled_back = txn.rollback();
+            assert_eq!(rolled_back, buffer);
+            for i in 0..4 {
+                assert_eq!(ptr::read(buffer.offset(i)), i as u32);
+            }
+
+            typed_dealloc(buffer, 4);
+        }
+    }
+
+    #[test]
+    fn test_commit() {
+        unsafe {
+            let buffer = typed_alloc::<u32>(4);
+            for i in 0..4 {
+                ptr::write(buffer.offset(i), i as u32);
+            }
+
+            let txn = begin_grow(buffer, 4, 8);
+            for i in 4..8 {
+                ptr::write(txn.new_ptr().offset(i), i as u32);
+            }
+
+            let grown = txn.commit();
+            for i in 0..8 {
+                assert_eq!(ptr::read(grown.offset(i)), i as u32);
+            }
+
+            typed_dealloc(grown, 8);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use std::ptr::NonNull;
+
+use {typed_dealloc, typed_realloc, OwnedSlice};
+
+/// An `io::Write` sink which accumulates everything written to it in a
+/// buffer allocated through this crate.
+///
+/// Anything that serializes to a writer, such as `write!` or
+/// `serde_json::to_writer`, can target a `BufWriter`. The buffer grows
+/// amortized, at least doubling, and a failure to grow is reported as an
+/// `io::ErrorKind::OutOfMemory` error rather than aborting the process.
+/// Nothing is buffered on the way in, so `flush` does nothing.
+#[derive(Default)]
+pub struct BufWriter {
+    alloc: OwnedSlice<u8>,
+}
+
+impl BufWriter {
+    /// Creates an empty `BufWriter`, which does not allocate until written
+    /// to.
+    pub fn new() -> BufWriter {
+        BufWriter { alloc: OwnedSlice::new() }
+    }
+
+    /// Creates an empty `BufWriter` with room for `cap` bytes.
+    ///
+    /// A capacity of 0 does not allocate. On failure, aborts the process.
+    pub fn with_capacity(cap: usize) -> BufWriter {
+        BufWriter { alloc: OwnedSlice::with_capacity(cap) }
+    }
+
+    /// The bytes written so far.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.alloc
+    }
+
+    /// The number of bytes written so far.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.alloc.len()
+    }
+
+    /// Whether nothing has been written.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.alloc.is_empty()
+    }
+
+    /// Returns a pointer to the bytes written and their number, giving up
+    /// ownership of the buffer.
+    ///
+    /// The allocation is shrunk to fit the bytes, so it can be freed with
+    /// `deallocate(ptr, len)`. If nothing was written, nothing is allocated
+    /// and the pointer is dangling.
+    pub fn into_inner(self) -> (*mut u8, usize) {
+        let (ptr, len, cap) = self.alloc.into_raw_parts();
+
+        let ptr = unsafe {
+            match (len, cap) {
+                (0, 0) => ptr,
+                (0, cap) => {
+                    typed_dealloc(ptr, cap);
+                    NonNull::dangling().as_ptr()
+                },
+                (len, cap) => typed_realloc(ptr, cap, len),
+            }
+        };
+
+        (ptr, len)
+    }
+}
+
+impl Write for BufWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.alloc.try_extend_from_slice(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::OutOfMemory, err))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::slice;
+    use {deallocate, BufWriter};
+
+    #[test]
+    fn test_write_formatted() {
+        let mut writer = BufWriter::new();
+        for i in 0..100 {
+            write!(writer, "{},", i).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let expected = (0..100).map(|i| format!("{},", i)).collect::<String>();
+        assert_eq!(writer.as_bytes(), expected.as_bytes());
+
+        let (ptr, len) = writer.into_inner();
+        assert_eq!(len, expected.len());
+        unsafe {
+            assert_eq!(slice::from_raw_parts(ptr, len), expected.as_bytes());
+            deallocate(ptr, len);
+        }
+    }
+
+    #[test]
+    fn test_into_inner_empty() {
+        let (_, len) = BufWriter::with_capacity(64).into_inner();
+        assert_eq!(len, 0);
+        assert!(BufWriter::new().is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+// `FailAfter` replaces the global allocator, so `alloc_group`'s failure
+// path is tested in its own binary.
+
+extern crate memalloc;
+
+use std::{ptr, slice};
+
+use memalloc::{alloc_group, dealloc_group, try_typed_alloc, typed_dealloc, FailAfter};
+#[cfg(feature = "stats")]
+use memalloc::live_bytes;
+
+#[global_allocator]
+static ALLOC: FailAfter = FailAfter::new(usize::MAX);
+
+const SIZES: &[(usize, usize)] = &[(8, 4), (4, 16), (1, 100)];
+
+#[test]
+fn test_alloc_group() {
+    // Capturing a backtrace allocates on every allocation, so with the
+    // `backtrace` feature the count can't be predicted.
+    if cfg!(feature = "backtrace") {
+        return;
+    }
+
+    unsafe {
+        let group = alloc_group(SIZES).unwrap();
+        for (&buffer, &(element_size, count)) in group.iter().zip(SIZES) {
+            ptr::write_bytes(buffer, 0xAA, element_size * count);
+            assert!(slice::from_raw_parts(buffer, element_size * count).iter().all(|&b| b == 0xAA));
+        }
+        dealloc_group(group, SIZES);
+
+        // With the `stats` feature, the registry allocates as it grows, so
+        // make it big enough up front.
+        let warm_up = (0..64).map(|_| try_typed_alloc::<u64>(8).unwrap()).collect::<Vec<_>>();
+        for buffer in warm_up {
+            typed_dealloc(buffer, 8);
+        }
+
+        // The group's own `Vec` and the first two buffers succeed, and the
+        // last buffer fails.
+        ALLOC.reset(SIZES.len() + 1);
+        let failed = alloc_group(SIZES);
+        ALLOC.reset(usize::MAX);
+        assert!(failed.is_none());
+
+        // The buffers made before the failure were freed.
+        #[cfg(feature = "stats")]
+        assert_eq!(live_bytes(), 0);
+
+        assert!(alloc_group(&[(usize::MAX, 2)]).is_none());
+        assert_eq!(alloc_group(&[(0, 10)]).unwrap().len(), 1);
+    }
+}
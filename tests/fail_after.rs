@@ -0,0 +1,50 @@
+// `FailAfter` replaces the global allocator, so it is tested in its own
+// binary.
+
+extern crate memalloc;
+
+use memalloc::{try_typed_alloc, try_typed_realloc, typed_dealloc, AllocError, FailAfter};
+
+#[global_allocator]
+static ALLOC: FailAfter = FailAfter::new(usize::MAX);
+
+const N: usize = 5;
+
+#[test]
+fn test_fails_nth_allocation() {
+    // Capturing a backtrace allocates on every allocation, so with the
+    // `backtrace` feature the count can't be predicted.
+    if cfg!(feature = "backtrace") {
+        return;
+    }
+
+    let mut buffers = Vec::with_capacity(N);
+
+    unsafe {
+        // With the `stats` feature, the registry allocates as it grows, so
+        // make it big enough up front.
+        let warm_up = (0..2 * N).map(|_| try_typed_alloc::<u64>(8).unwrap()).collect::<Vec<_>>();
+        for buffer in warm_up {
+            typed_dealloc(buffer, 8);
+        }
+
+        ALLOC.reset(N);
+        for _ in 0..N - 1 {
+            buffers.push(try_typed_alloc::<u64>(8));
+        }
+        let failed = try_typed_alloc::<u64>(8);
+        let grown = try_typed_realloc(buffers[0].unwrap(), 8, 1024);
+        ALLOC.reset(usize::MAX);
+
+        assert_eq!(failed, Err(AllocError::OutOfMemory));
+        // Later allocations keep failing until the count is reset.
+        assert_eq!(grown, Err(AllocError::OutOfMemory));
+
+        for buffer in buffers {
+            typed_dealloc(buffer.unwrap(), 8);
+        }
+
+        let buffer = try_typed_alloc::<u64>(8).unwrap();
+        typed_dealloc(buffer, 8);
+    }
+}
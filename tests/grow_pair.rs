@@ -0,0 +1,59 @@
+// `FailAfter` replaces the global allocator, so `grow_pair`'s failure path
+// is tested in its own binary.
+
+extern crate memalloc;
+
+use std::{ptr, slice};
+
+use memalloc::{grow_pair, try_typed_alloc, typed_alloc, typed_dealloc, FailAfter};
+#[cfg(feature = "stats")]
+use memalloc::allocation_size;
+
+#[global_allocator]
+static ALLOC: FailAfter = FailAfter::new(usize::MAX);
+
+#[test]
+fn test_grow_pair_second_fails() {
+    // Capturing a backtrace allocates on every allocation, so with the
+    // `backtrace` feature the count can't be predicted.
+    if cfg!(feature = "backtrace") {
+        return;
+    }
+
+    unsafe {
+        // With the `stats` feature, the registry allocates as it grows, so
+        // make it big enough up front.
+        let warm_up = (0..64).map(|_| try_typed_alloc::<u64>(8).unwrap()).collect::<Vec<_>>();
+        for buffer in warm_up {
+            typed_dealloc(buffer, 8);
+        }
+
+        let a = typed_alloc::<u32>(16);
+        let b = typed_alloc::<u32>(16);
+        for i in 0..16 {
+            ptr::write(a.add(i), i as u32);
+            ptr::write(b.add(i), 100 + i as u32);
+        }
+
+        // The fresh allocation for `a` succeeds, and growing `b` fails.
+        ALLOC.reset(2);
+        let grown = grow_pair(a, b, 16, 1 << 20);
+        ALLOC.reset(usize::MAX);
+        assert!(grown.is_none());
+
+        // Both are left where they were, with their sizes and contents.
+        #[cfg(feature = "stats")]
+        {
+            assert_eq!(allocation_size(a), Some(64));
+            assert_eq!(allocation_size(b), Some(64));
+        }
+        assert_eq!(slice::from_raw_parts(a, 16), &(0..16).collect::<Vec<_>>()[..]);
+        assert_eq!(slice::from_raw_parts(b, 16), &(100..116).collect::<Vec<_>>()[..]);
+
+        let (a, b) = grow_pair(a, b, 16, 32).unwrap();
+        assert_eq!(ptr::read(a.add(15)), 15);
+        assert_eq!(ptr::read(b.add(15)), 115);
+        typed_dealloc(a, 32);
+        typed_dealloc(b, 32);
+    }
+}
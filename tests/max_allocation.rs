@@ -0,0 +1,59 @@
+// The allocation limit is process-wide, so it is tested in its own binary
+// to keep it from affecting the rest of the test suite.
+
+extern crate memalloc;
+
+use std::ptr;
+use std::sync::Mutex;
+
+use memalloc::{grow_pair, max_allocation, set_max_allocation, try_typed_alloc,
+               try_typed_realloc, typed_alloc, typed_dealloc, AllocError};
+
+// Serializes the tests in this file, which each set and then reset the
+// limit.
+static LIMIT: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_max_allocation() {
+    let _guard = LIMIT.lock().unwrap();
+
+    assert_eq!(max_allocation(), usize::MAX);
+    set_max_allocation(1024);
+
+    unsafe {
+        assert_eq!(try_typed_alloc::<u8>(1025).unwrap_err(), AllocError::ExceedsLimit);
+        // The limit applies to bytes, not elements.
+        assert_eq!(try_typed_alloc::<u32>(257).unwrap_err(), AllocError::ExceedsLimit);
+
+        let buffer = try_typed_alloc::<u32>(256).unwrap();
+        assert_eq!(try_typed_realloc(buffer, 256, 512).unwrap_err(), AllocError::ExceedsLimit);
+        typed_dealloc(buffer, 256);
+
+        set_max_allocation(usize::MAX);
+        let buffer = typed_alloc::<u32>(512);
+        typed_dealloc(buffer, 512);
+    }
+}
+
+#[test]
+fn test_grow_pair_rolls_back() {
+    let _guard = LIMIT.lock().unwrap();
+
+    unsafe {
+        let a = typed_alloc::<u8>(4);
+        let b = typed_alloc::<u64>(4);
+        ptr::write(a.offset(3), 3);
+        ptr::write(b.offset(3), 33);
+
+        // 200 bytes of `a` fit under the limit, 1600 bytes of `b` do not.
+        set_max_allocation(1024);
+        assert!(grow_pair(a, b, 4, 200).is_none());
+        set_max_allocation(usize::MAX);
+
+        // Both allocations are intact at their original size.
+        assert_eq!(ptr::read(a.offset(3)), 3);
+        assert_eq!(ptr::read(b.offset(3)), 33);
+        typed_dealloc(a, 4);
+        typed_dealloc(b, 4);
+    }
+}
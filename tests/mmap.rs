@@ -0,0 +1,127 @@
+// The mmap threshold is process-wide, so it is tested in its own binary to
+// keep it from affecting the rest of the test suite.
+
+#![cfg(all(unix, feature = "mmap"))]
+
+extern crate memalloc;
+
+use std::{ptr, slice};
+use std::io::Write;
+use std::sync::Mutex;
+
+use memalloc::{alloc_uninit_box, checked_realloc, grow_pair, is_mapped, mmap_threshold, realloc_typed,
+               set_mmap_threshold, try_typed_realloc, typed_alloc, typed_dealloc, typed_realloc,
+               BufWriter, OwnedSlice};
+
+const THRESHOLD: usize = 1 << 20;
+
+// Serializes the tests in this file, which each set and then reset the
+// threshold.
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_mmap_threshold() {
+    let _guard = LOCK.lock().unwrap();
+
+    assert_eq!(mmap_threshold(), usize::MAX);
+    set_mmap_threshold(THRESHOLD);
+
+    unsafe {
+        let small = typed_alloc::<u8>(THRESHOLD / 2);
+        let large = typed_alloc::<u8>(THRESHOLD);
+        assert!(!is_mapped(small));
+        assert!(is_mapped(large));
+        assert!(is_mapped(large.add(THRESHOLD - 1)));
+
+        ptr::write_bytes(small, 1, THRESHOLD / 2);
+        ptr::write_bytes(large, 2, THRESHOLD);
+
+        // Growing past the threshold moves the values into a mapping, and
+        // shrinking below it moves them back to the heap.
+        let small = typed_realloc(small, THRESHOLD / 2, 2 * THRESHOLD);
+        assert!(is_mapped(small));
+        assert!(slice::from_raw_parts(small, THRESHOLD / 2).iter().all(|&b| b == 1));
+
+        let large = typed_realloc(large, THRESHOLD, 16);
+        assert!(!is_mapped(large));
+        assert!(slice::from_raw_parts(large, 16).iter().all(|&b| b == 2));
+
+        // A mapping can grow into another.
+        let small = typed_realloc(small, 2 * THRESHOLD, 4 * THRESHOLD);
+        assert!(is_mapped(small));
+        assert_eq!(ptr::read(small.add(THRESHOLD / 2 - 1)), 1);
+
+        // Memory mapped before the threshold is lifted is still unmapped.
+        set_mmap_threshold(usize::MAX);
+        typed_dealloc(small, 4 * THRESHOLD);
+        assert!(!is_mapped(small));
+        typed_dealloc(large, 16);
+    }
+}
+
+#[test]
+fn test_fallible_growth_past_threshold() {
+    let _guard = LOCK.lock().unwrap();
+    set_mmap_threshold(4096);
+
+    // The writer grows through `try_typed_realloc`, which must not hand the
+    // mapping to a `Vec`.
+    let mut writer = BufWriter::with_capacity(8192);
+    writer.write_all(&[1u8; 20000]).unwrap();
+    writer.write_all(&[2u8; 50000]).unwrap();
+    assert_eq!(writer.len(), 70000);
+    assert!(is_mapped(writer.as_bytes().as_ptr()));
+    assert!(writer.as_bytes()[..20000].iter().all(|&b| b == 1));
+    drop(writer);
+
+    let mut slice = OwnedSlice::new();
+    for i in 0..10000u32 {
+        slice.push(i);
+    }
+    assert_eq!(slice[9999], 9999);
+    drop(slice);
+
+    unsafe {
+        let buffer = typed_alloc::<u8>(8192);
+        ptr::write_bytes(buffer, 3, 8192);
+        let buffer = try_typed_realloc(buffer, 8192, 16384).unwrap();
+        let buffer = checked_realloc(buffer, 16384, Some(16384), 32768).unwrap();
+        let buffer = realloc_typed::<u8, u8>(buffer, 32768, 65536);
+        assert!(is_mapped(buffer));
+        assert_eq!(ptr::read(buffer.add(8191)), 3);
+        typed_dealloc(buffer, 65536);
+
+        let a = typed_alloc::<u64>(1024);
+        let b = typed_alloc::<u64>(1024);
+        let (a, b) = grow_pair(a, b, 1024, 4096).unwrap();
+        assert!(is_mapped(a) && is_mapped(b));
+        typed_dealloc(a, 4096);
+        typed_dealloc(b, 4096);
+
+        // A box is freed by the global allocator, so it is never mapped.
+        let boxed = alloc_uninit_box::<u8>(1 << 16);
+        assert!(!is_mapped(boxed.as_ptr()));
+        drop(boxed);
+    }
+
+    set_mmap_threshold(usize::MAX);
+}
+
+#[test]
+#[cfg(feature = "bytes")]
+fn test_into_bytes_mut_unmaps() {
+    let _guard = LOCK.lock().unwrap();
+    set_mmap_threshold(4096);
+
+    unsafe {
+        let buffer = typed_alloc::<u8>(8192);
+        ptr::write_bytes(buffer, 5, 100);
+        assert!(is_mapped(buffer));
+
+        let bytes = memalloc::into_bytes_mut(buffer, 100, 8192);
+        assert!(!is_mapped(bytes.as_ptr()));
+        assert_eq!(&bytes[..], &[5; 100][..]);
+    }
+
+    set_mmap_threshold(usize::MAX);
+}
@@ -0,0 +1,163 @@
+// A model-based checker for the allocation functions, shared by the seeded
+// replay test in tests/round_trip.rs and the fuzz target in fuzz/.
+//
+// Operations are applied to a handful of real buffers and, alongside, to a
+// shadow copy of what each buffer should contain. After every operation the
+// real buffers are compared against their shadows, which catches size
+// tracking mistakes and data lost when a reallocation relocates.
+
+#![allow(dead_code)]
+
+use std::{cmp, slice};
+
+use memalloc::{allocate, deallocate, grow_from_known, reallocate, try_typed_realloc};
+
+/// The number of buffers operations are spread across.
+pub const SLOTS: usize = 8;
+
+/// The largest buffer operations will ask for.
+pub const MAX_SIZE: usize = 4096;
+
+/// The ways of growing a buffer, all of which must behave the same.
+#[derive(Debug, Clone, Copy)]
+pub enum GrowWith {
+    Reallocate,
+    GrowFromKnown,
+    TryRealloc,
+}
+
+/// An operation on the buffer in `slot`. Operations which don't apply to
+/// the slot's state, such as freeing an empty slot, are skipped.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    /// Allocates `size` bytes into an empty slot, filled from `seed`.
+    Alloc { slot: usize, size: usize, seed: u8 },
+    /// Grows a buffer by `extra` bytes, filling them from `seed`.
+    Grow { slot: usize, extra: usize, seed: u8, via: GrowWith },
+    /// Shrinks a buffer to `size` bytes, and at least by half.
+    Shrink { slot: usize, size: usize },
+    /// Overwrites the byte at `offset`, modulo the buffer size.
+    Write { slot: usize, offset: usize, byte: u8 },
+    /// Frees a buffer.
+    Free { slot: usize },
+}
+
+struct Buffer {
+    ptr: *mut u8,
+    shadow: Vec<u8>,
+}
+
+/// The real buffers and their shadows.
+pub struct Model {
+    buffers: Vec<Option<Buffer>>,
+}
+
+impl Model {
+    pub fn new() -> Model {
+        Model { buffers: (0..SLOTS).map(|_| None).collect() }
+    }
+
+    /// Applies `op` to the real buffers and the shadows, then checks every
+    /// live buffer against its shadow.
+    pub fn apply(&mut self, op: Op) {
+        unsafe { self.apply_raw(op) };
+        self.check(op);
+    }
+
+    unsafe fn apply_raw(&mut self, op: Op) {
+        match op {
+            Op::Alloc { slot, size, seed } => {
+                let size = clamp(size);
+                if self.buffers[slot % SLOTS].is_some() {
+                    return;
+                }
+
+                let ptr = allocate(size);
+                let shadow = pattern(seed, size);
+                write(ptr, 0, &shadow);
+                self.buffers[slot % SLOTS] = Some(Buffer { ptr, shadow });
+            },
+            Op::Grow { slot, extra, seed, via } => {
+                let buffer = match self.buffers[slot % SLOTS] {
+                    Some(ref mut buffer) => buffer,
+                    None => return,
+                };
+                let old_size = buffer.shadow.len();
+                let new_size = old_size + 1 + extra % MAX_SIZE;
+
+                buffer.ptr = match via {
+                    GrowWith::Reallocate => reallocate(buffer.ptr, old_size, new_size),
+                    GrowWith::GrowFromKnown => grow_from_known(buffer.ptr, old_size, new_size),
+                    GrowWith::TryRealloc => try_typed_realloc(buffer.ptr, old_size, new_size)
+                        .expect("growing failed"),
+                };
+
+                let tail = pattern(seed, new_size - old_size);
+                check_buffer(buffer.ptr, &buffer.shadow, op);
+                write(buffer.ptr, old_size, &tail);
+                buffer.shadow.extend_from_slice(&tail);
+            },
+            Op::Shrink { slot, size } => {
+                let buffer = match self.buffers[slot % SLOTS] {
+                    Some(ref mut buffer) => buffer,
+                    None => return,
+                };
+                let old_size = buffer.shadow.len();
+                let new_size = cmp::min(clamp(size), old_size.div_ceil(2));
+
+                buffer.ptr = reallocate(buffer.ptr, old_size, new_size);
+                buffer.shadow.truncate(new_size);
+            },
+            Op::Write { slot, offset, byte } => {
+                if let Some(ref mut buffer) = self.buffers[slot % SLOTS] {
+                    let offset = offset % buffer.shadow.len();
+                    *buffer.ptr.add(offset) = byte;
+                    buffer.shadow[offset] = byte;
+                }
+            },
+            Op::Free { slot } => {
+                if let Some(buffer) = self.buffers[slot % SLOTS].take() {
+                    deallocate(buffer.ptr, buffer.shadow.len());
+                }
+            },
+        }
+    }
+
+    fn check(&self, op: Op) {
+        for buffer in self.buffers.iter().flatten() {
+            unsafe { check_buffer(buffer.ptr, &buffer.shadow, op) };
+        }
+    }
+}
+
+impl Drop for Model {
+    fn drop(&mut self) {
+        for slot in 0..SLOTS {
+            unsafe { self.apply_raw(Op::Free { slot }) };
+        }
+    }
+}
+
+/// Clamps a requested size into `1..=MAX_SIZE`.
+fn clamp(size: usize) -> usize {
+    1 + size % MAX_SIZE
+}
+
+/// `len` bytes derived from `seed`, distinct enough that a misplaced copy
+/// shows up.
+fn pattern(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| seed.wrapping_add((i * 31 % 251) as u8)).collect()
+}
+
+unsafe fn write(ptr: *mut u8, offset: usize, bytes: &[u8]) {
+    slice::from_raw_parts_mut(ptr.add(offset), bytes.len()).copy_from_slice(bytes);
+}
+
+unsafe fn check_buffer(ptr: *mut u8, shadow: &[u8], op: Op) {
+    let actual = slice::from_raw_parts(ptr, shadow.len());
+    if actual != shadow {
+        let at = actual.iter().zip(shadow).position(|(a, b)| a != b).unwrap();
+        panic!("after {:?}, byte {} of {} is {:#x} but should be {:#x}",
+               op, at, shadow.len(), actual[at], shadow[at]);
+    }
+}
@@ -0,0 +1,50 @@
+// The OOM behavior is process-wide, so it is tested in its own binary to
+// keep it from affecting the rest of the test suite.
+
+extern crate memalloc;
+
+use std::panic;
+
+use memalloc::{set_oom_behavior, typed_alloc, typed_dealloc, typed_realloc, OomBehavior};
+
+// Too large for any allocator to provide, but small enough that there is
+// room for red zones without overflowing `isize`.
+const HUGE: usize = isize::MAX as usize / 2;
+
+fn handler(bytes: usize) -> ! {
+    panic!("custom handler called for {} bytes", bytes)
+}
+
+fn panic_message(result: std::thread::Result<()>) -> String {
+    let payload = result.unwrap_err();
+    payload.downcast_ref::<String>().cloned().unwrap_or_default()
+}
+
+#[test]
+fn test_oom_behavior() {
+    unsafe {
+        set_oom_behavior(OomBehavior::Custom(handler));
+        let message = panic_message(panic::catch_unwind(|| {
+            typed_alloc::<u8>(HUGE);
+        }));
+        assert!(message.starts_with("custom handler called for"), "{}", message);
+
+        // Growing goes through the same hook, and leaves the original intact.
+        let buffer = typed_alloc::<u8>(16);
+        let message = panic_message(panic::catch_unwind(|| {
+            typed_realloc(buffer, 16, HUGE);
+        }));
+        assert!(message.starts_with("custom handler called for"), "{}", message);
+        typed_dealloc(buffer, 16);
+
+        set_oom_behavior(OomBehavior::Panic);
+        let message = panic_message(panic::catch_unwind(|| {
+            typed_alloc::<u8>(HUGE);
+        }));
+        assert!(message.starts_with("memalloc: out of memory allocating"), "{}", message);
+
+        set_oom_behavior(OomBehavior::Abort);
+        let buffer = typed_alloc::<u8>(16);
+        typed_dealloc(buffer, 16);
+    }
+}
@@ -0,0 +1,89 @@
+// Drives the core functions with sizes at the edge of what fits in the
+// address space, for element types of several sizes. Every request must
+// either allocate or fail cleanly, never under-allocate through a wrapped
+// size computation.
+
+extern crate memalloc;
+
+use std::{mem, panic, ptr};
+
+use memalloc::{alloc_aligned_const, dealloc_aligned_const, try_typed_alloc, try_typed_realloc,
+               typed_alloc, typed_dealloc, AllocError};
+
+/// The boundary sizes for `T`, and how a request for each must fail.
+fn boundaries<T>() -> Vec<(usize, AllocError)> {
+    let size = mem::size_of::<T>();
+    let max = isize::MAX as usize;
+
+    let mut sizes = vec![
+        (usize::MAX, AllocError::CapacityOverflow),
+        (usize::MAX / size, AllocError::CapacityOverflow),
+        (max / size + 1, AllocError::CapacityOverflow),
+        (max / size, AllocError::OutOfMemory),
+    ];
+    if let Some(wrapping) = (usize::MAX / size).checked_add(1) {
+        sizes.push((wrapping, AllocError::CapacityOverflow));
+    }
+
+    sizes
+}
+
+fn check<T>() {
+    for (size, err) in boundaries::<T>() {
+        unsafe {
+            assert_eq!(try_typed_alloc::<T>(size), Err(err), "size {}", size);
+
+            let small = typed_alloc::<T>(4);
+            ptr::write_bytes(small as *mut u8, 0xAB, 4 * mem::size_of::<T>());
+            assert_eq!(try_typed_realloc(small, 4, size), Err(err), "size {}", size);
+            assert_eq!(ptr::read(small as *const u8), 0xAB);
+            typed_dealloc(small, 4);
+        }
+
+        if err == AllocError::CapacityOverflow {
+            let result = panic::catch_unwind(|| unsafe { typed_alloc::<T>(size) });
+            assert!(result.is_err(), "size {}", size);
+        }
+    }
+}
+
+#[test]
+fn test_u8() {
+    check::<u8>();
+}
+
+#[test]
+fn test_u32() {
+    check::<u32>();
+}
+
+#[test]
+fn test_odd_size() {
+    check::<[u8; 24]>();
+}
+
+#[test]
+fn test_large() {
+    check::<[u64; 64]>();
+}
+
+#[test]
+fn test_aligned_const() {
+    // Times the size of a `u64`, this wraps around to a small size.
+    let wrapping = usize::MAX / 4;
+
+    let result = panic::catch_unwind(|| unsafe { alloc_aligned_const::<u64, 64>(wrapping) });
+    assert!(result.is_err());
+
+    unsafe {
+        let ptr = alloc_aligned_const::<u64, 64>(4);
+        let addr = ptr as usize;
+        let result = panic::catch_unwind(move || {
+            dealloc_aligned_const::<u64, 64>(addr as *mut u64, wrapping)
+        });
+        assert!(result.is_err());
+
+        // The panic came before anything was freed.
+        dealloc_aligned_const::<u64, 64>(ptr, 4);
+    }
+}
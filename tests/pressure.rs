@@ -0,0 +1,60 @@
+// The pressure hook is process-wide, so it is tested in its own binary to
+// keep it from affecting the rest of the test suite.
+
+extern crate memalloc;
+
+use std::ptr;
+use std::sync::Mutex;
+
+use memalloc::{realloc_pressure_aware, set_pressure_hook, typed_dealloc, MemoryPressure};
+#[cfg(feature = "stats")]
+use memalloc::allocation_size;
+
+// Serializes the tests in this file, which each install their own hook.
+static HOOK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_critical_growth_is_exact() {
+    let _guard = HOOK.lock().unwrap();
+    set_pressure_hook(|| MemoryPressure::Critical);
+
+    unsafe {
+        let (mut buffer, mut cap) = realloc_pressure_aware::<u64>(ptr::null_mut(), 0, 3);
+        assert_eq!(cap, 3);
+        ptr::write(buffer, 7);
+
+        for size in 4..50 {
+            let (grown, granted) = realloc_pressure_aware(buffer, cap, size);
+            assert_eq!(granted, size);
+            #[cfg(feature = "stats")]
+            assert_eq!(allocation_size(grown), Some(size * 8));
+            buffer = grown;
+            cap = granted;
+        }
+        assert_eq!(ptr::read(buffer), 7);
+
+        typed_dealloc(buffer, cap);
+    }
+}
+
+#[test]
+fn test_growth_follows_pressure() {
+    let _guard = HOOK.lock().unwrap();
+
+    unsafe {
+        set_pressure_hook(|| MemoryPressure::Normal);
+        let (buffer, cap) = realloc_pressure_aware::<u8>(ptr::null_mut(), 0, 16);
+        let (buffer, cap) = realloc_pressure_aware(buffer, cap, 17);
+        assert_eq!(cap, 32);
+
+        set_pressure_hook(|| MemoryPressure::Elevated);
+        let (buffer, cap) = realloc_pressure_aware(buffer, cap, 33);
+        assert_eq!(cap, 48);
+
+        // Shrinking is exact whatever the pressure.
+        let (buffer, cap) = realloc_pressure_aware(buffer, cap, 10);
+        assert_eq!(cap, 10);
+
+        typed_dealloc(buffer, cap);
+    }
+}
@@ -0,0 +1,240 @@
+// The registry is process-wide, so the tests of its totals run in their own
+// binary, away from the allocations of the rest of the test suite.
+
+#![cfg(feature = "stats")]
+
+extern crate memalloc;
+
+#[cfg(feature = "backtrace")]
+use std::hint;
+use std::panic;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use memalloc::{allocate, allocation_size, alloc_try_init, deallocate, live_bytes,
+               size_histogram, typed_alloc, typed_dealloc, typed_realloc, with_aligned, Pool};
+
+// Serializes the tests in this file, which each expect to be the only one
+// with live allocations.
+static REGISTRY: Mutex<()> = Mutex::new(());
+
+fn serialize() -> MutexGuard<'static, ()> {
+    REGISTRY.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[test]
+fn test_across_threads() {
+    let _guard = serialize();
+
+    // Addresses are sent between threads as integers, since raw pointers
+    // aren't `Send`.
+    let allocated = thread::spawn(|| {
+        (0..64).map(|_| unsafe { typed_alloc::<u64>(100) } as usize).collect::<Vec<_>>()
+    }).join().unwrap();
+    assert_eq!(live_bytes(), 64 * 800);
+
+    // Other threads allocate in a hot loop while the buffers are grown, so
+    // they are handed the freed addresses and their records must survive.
+    let stop = Arc::new(AtomicBool::new(false));
+    let churn = (0..4).map(|_| {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                unsafe {
+                    let buffer = typed_alloc::<u64>(100);
+                    assert_eq!(allocation_size(buffer), Some(800));
+                    typed_dealloc(buffer, 100);
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    let grown = thread::spawn(move || {
+        allocated.into_iter()
+            .map(|addr| unsafe { typed_realloc(addr as *mut u64, 100, 1000) } as usize)
+            .collect::<Vec<_>>()
+    }).join().unwrap();
+    stop.store(true, Ordering::Relaxed);
+    for thread in churn {
+        thread.join().unwrap();
+    }
+    assert_eq!(live_bytes(), 64 * 8000);
+    assert_eq!(size_histogram(), [(8000, 64)]);
+    for &addr in &grown {
+        assert_eq!(allocation_size(addr as *const u64), Some(8000));
+    }
+
+    thread::spawn(move || {
+        for addr in grown {
+            unsafe { typed_dealloc(addr as *mut u64, 1000) };
+        }
+    }).join().unwrap();
+    assert_eq!(live_bytes(), 0);
+    assert!(size_histogram().is_empty());
+}
+
+#[test]
+fn test_size_histogram() {
+    let _guard = serialize();
+
+    unsafe {
+        let small = (0..3).map(|_| typed_alloc::<u64>(4)).collect::<Vec<_>>();
+        let large = (0..2).map(|_| allocate(100)).collect::<Vec<_>>();
+
+        assert_eq!(size_histogram(), [(32, 3), (100, 2)]);
+
+        for ptr in small {
+            typed_dealloc(ptr, 4);
+        }
+        assert_eq!(size_histogram(), [(100, 2)]);
+
+        for ptr in large {
+            deallocate(ptr, 100);
+        }
+        assert!(size_histogram().is_empty());
+    }
+}
+
+#[test]
+fn test_alloc_try_init_error() {
+    let _guard = serialize();
+
+    let result = unsafe {
+        alloc_try_init::<u32, _, _>(10, |i| if i == 5 { Err("init failed") } else { Ok(i as u32) })
+    };
+
+    assert_eq!(result.err(), Some("init failed"));
+    assert_eq!(live_bytes(), 0);
+}
+
+#[test]
+fn test_pool_compact() {
+    let _guard = serialize();
+
+    let mut pool = Pool::<u64>::new(4);
+    let slots = (0..8).map(|_| pool.alloc()).collect::<Vec<_>>();
+    let before = live_bytes();
+
+    unsafe {
+        for &slot in &slots[3..] {
+            pool.free(slot);
+        }
+    }
+
+    // Only the emptied slab is freed.
+    pool.compact();
+    assert_eq!(before - live_bytes(), 4 * 8);
+}
+
+#[test]
+fn test_with_aligned_frees() {
+    let _guard = serialize();
+
+    unsafe {
+        with_aligned(256, 32, |buffer| {
+            assert!(live_bytes() >= 256);
+            (buffer as *mut [u64; 4]).write([1; 4]);
+        });
+    }
+    assert_eq!(live_bytes(), 0);
+
+    let result = panic::catch_unwind(|| unsafe {
+        with_aligned(256, 32, |_| panic!("scratch work failed"))
+    });
+    assert!(result.is_err());
+    assert_eq!(live_bytes(), 0);
+}
+
+// A relocated buffer's old address can be handed out again by another
+// thread's allocation, which clears the entry, so this is only checked here.
+#[test]
+#[cfg(debug_assertions)]
+fn test_relocated_to() {
+    let _guard = serialize();
+
+    unsafe {
+        let buffer = typed_alloc::<u8>(64);
+
+        // Shrinking always relocates.
+        let moved = typed_realloc(buffer, 64, 8);
+        assert_eq!(memalloc::relocated_to(buffer as *const u8), Some(moved as usize));
+        typed_dealloc(moved, 8);
+    }
+}
+
+/// Three `u64`s, freed on drop even if an assertion fails first, so that
+/// the other tests never see them.
+#[cfg(feature = "backtrace")]
+struct Leak(*mut u64);
+
+#[cfg(feature = "backtrace")]
+impl Leak {
+    /// Allocates outside of the test itself, and not as a tail call, so
+    /// that the allocating frame keeps its name in release builds too.
+    #[inline(never)]
+    fn allocate_leak() -> Leak {
+        Leak(hint::black_box(unsafe { typed_alloc::<u64>(3) }))
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl Drop for Leak {
+    fn drop(&mut self) {
+        unsafe { typed_dealloc(self.0, 3) };
+    }
+}
+
+#[test]
+#[cfg(feature = "backtrace")]
+fn test_dump_leaks() {
+    let _guard = serialize();
+
+    let leaked = Leak::allocate_leak();
+
+    let report = memalloc::dump_leaks();
+    assert!(report.contains(&format!("24 bytes at {:#x}", leaked.0 as usize)), "{}", report);
+    assert!(report.contains("allocate_leak"), "{}", report);
+
+    drop(leaked);
+    assert!(memalloc::dump_leaks().is_empty());
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn test_prometheus_metrics() {
+    let _guard = serialize();
+
+    // The counters keep the totals of the tests which ran before this one.
+    fn value(metrics: &str, name: &str) -> usize {
+        metrics.lines()
+            .filter(|line| line.starts_with(name))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum()
+    }
+
+    let before = memalloc::prometheus_metrics();
+
+    unsafe {
+        let buffers = (0..3).map(|_| typed_alloc::<u64>(4)).collect::<Vec<_>>();
+        let grown = typed_realloc(typed_alloc::<u8>(100), 100, 1000);
+
+        let metrics = memalloc::prometheus_metrics();
+        let lines = metrics.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"memalloc_live_allocations 4"));
+        assert!(lines.contains(&"memalloc_live_bytes 1096"));
+        assert!(lines.contains(&"memalloc_allocation_size_bytes_bucket{le=\"32\"} 3"));
+        assert!(lines.contains(&"memalloc_allocation_size_bytes_bucket{le=\"1024\"} 4"));
+        assert!(lines.contains(&"memalloc_allocation_size_bytes_bucket{le=\"+Inf\"} 4"));
+
+        let allocations = "memalloc_allocations_total ";
+        assert_eq!(value(&metrics, allocations) - value(&before, allocations), 4);
+        let reallocs = "memalloc_reallocations_total{";
+        assert_eq!(value(&metrics, reallocs) - value(&before, reallocs), 1);
+
+        for buffer in buffers {
+            typed_dealloc(buffer, 4);
+        }
+        typed_dealloc(grown, 1000);
+    }
+}
@@ -0,0 +1,38 @@
+// Size classes are process-wide, and must be registered before any
+// allocation of the type is made, so they are tested in their own binary.
+
+extern crate memalloc;
+
+use std::ptr;
+
+use memalloc::{register_size_classes, size_class, typed_alloc, typed_dealloc, typed_realloc};
+
+#[test]
+fn test_size_classes() {
+    assert_eq!(size_class::<u8>(20), 20);
+    unsafe {
+        register_size_classes::<u8>(&[256, 16, 64]);
+    }
+
+    assert_eq!(size_class::<u8>(1), 16);
+    assert_eq!(size_class::<u8>(16), 16);
+    assert_eq!(size_class::<u8>(20), 64);
+    assert_eq!(size_class::<u8>(300), 300);
+    // Other types are unaffected.
+    assert_eq!(size_class::<u16>(20), 20);
+
+    unsafe {
+        // The whole granted capacity is usable.
+        let buffer = typed_alloc::<u8>(20);
+        ptr::write_bytes(buffer, 1, size_class::<u8>(20));
+
+        // Growing within the class is a no-op.
+        assert_eq!(typed_realloc(buffer, 20, 60), buffer);
+
+        let buffer = typed_realloc(buffer, 60, 100);
+        assert_eq!(ptr::read(buffer.offset(63)), 1);
+        ptr::write_bytes(buffer, 2, 256);
+
+        typed_dealloc(buffer, 100);
+    }
+}